@@ -1,28 +1,220 @@
+use crate::chat::ObfuscateMode;
+use crate::data_types::DEFAULT_MAX_PACKET_SIZE;
+use crate::versions;
+use std::io::IsTerminal;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::time::Duration;
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct CommandLineArguments {
     pub get_favicon: bool,
+    pub favicon_hash: bool,
     pub raw_response: bool,
+    pub pretty: bool,
+    pub raw_description: bool,
+    pub lossy: bool,
+    pub max_packet_size: usize,
     pub verbose: bool,
+    pub quiet: bool,
+    pub dump_packets: bool,
+    pub capture: Option<PathBuf>,
+    pub no_color: bool,
+    pub color_policy: ColorPolicy,
+    pub list_colors: bool,
     pub open_to_lan: bool,
     pub host: String,
     pub port: u16,
+    pub address_family: AddressFamily,
+    pub handshake_host: Option<String>,
+    pub forge: Option<ForgeMarker>,
+    pub protocol: i32,
+    pub deadline: Option<Duration>,
+    pub wait_online: Option<Duration>,
+    pub wait_interval: Duration,
+    pub only_latency: bool,
+    pub unix_socket: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub no_ping: bool,
+    pub ping_payload: Option<i64>,
+    pub tcp_check: bool,
+    pub fail_on_empty: bool,
+    pub fail_if_full: bool,
+    pub expect_version: Option<String>,
+    pub expect_protocol: Option<i32>,
+    pub match_protocol: bool,
+    pub protocol_fallback: bool,
+    pub fail_on_mismatch_only: bool,
+    pub bind_port: Option<u16>,
+    pub buffer_size: Option<usize>,
+    pub lan_interface: Ipv4Addr,
+    pub timestamps: bool,
+    pub once: bool,
+    pub output: Option<PathBuf>,
+    pub append: bool,
+    pub obfuscate_mode: ObfuscateMode,
+    pub format: Option<String>,
+    pub compact: bool,
+    pub named_protocol: bool,
+    pub describe: bool,
+    pub timing: bool,
+    pub early_hints: bool,
+    pub motd_width: usize,
+    pub motd_lines: Option<usize>,
+    pub clean_motd: bool,
+    pub login_probe: bool,
+    pub username: String,
+    pub brand: Option<String>,
+    pub probe_protocols: Vec<i32>,
+    pub scan_motd: Option<u32>,
 }
 
-impl CommandLineArguments {
-    pub fn parse<T: Iterator<Item = String>>(args: &mut T) -> Result<Self, String> {
-        let mut arguments = CommandLineArguments {
+// Whether ANSI colors and other font styles should be used. Resolved once at startup from --no-color and the
+// environment, based on http://bixense.com/clicolors/
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorPolicy {
+    Never,
+    Always,
+    // Only when the stream is a terminal
+    Auto,
+}
+
+impl ColorPolicy {
+    // --no-color wins over everything else, then NO_COLOR=1 and finally CLICOLOR_FORCE=1
+    pub fn resolve<E: Fn(&str) -> Option<String>>(no_color: bool, env: E) -> Self {
+        if no_color || env("NO_COLOR").is_some_and(|v| v == "1") {
+            ColorPolicy::Never
+        } else if env("CLICOLOR_FORCE").is_some_and(|v| v == "1") {
+            ColorPolicy::Always
+        } else {
+            ColorPolicy::Auto
+        }
+    }
+
+    // stdout and stderr can be redirected independently, so the terminal check is done per stream
+    pub fn use_colors<T: IsTerminal>(self, stream_handle: &T) -> bool {
+        match self {
+            ColorPolicy::Never => false,
+            ColorPolicy::Always => true,
+            ColorPolicy::Auto => stream_handle.is_terminal(),
+        }
+    }
+}
+
+// Which kind of IP address to connect to when the host resolves to both
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AddressFamily {
+    // The first address returned by the resolver
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+// Marker that modded clients append to the handshake address so Forge servers send their full mod list
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ForgeMarker {
+    // Forge for 1.7 to 1.12
+    Fml,
+    // Forge for 1.13 and newer
+    Fml2,
+}
+
+impl ForgeMarker {
+    pub fn handshake_suffix(self) -> &'static str {
+        match self {
+            ForgeMarker::Fml => "\0FML\0",
+            ForgeMarker::Fml2 => "\0FML2\0",
+        }
+    }
+}
+
+impl Default for CommandLineArguments {
+    fn default() -> Self {
+        CommandLineArguments {
             // General flags
             raw_response: false,
+            pretty: false,
+            raw_description: false,
+            lossy: false,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
             verbose: false,
+            quiet: false,
+            no_color: false,
+            color_policy: ColorPolicy::Auto,
+            list_colors: false,
+            dump_packets: false,
+            capture: None,
 
             // Flags for Open to LAN mode
             open_to_lan: false,
 
             // Flags for ping mode
             get_favicon: false,
+            favicon_hash: false,
             host: "".to_owned(),
             port: 25565,
-        };
+            address_family: AddressFamily::Any,
+            handshake_host: None,
+            forge: None,
+            protocol: versions::MIN_MINECRAFT_PROTOCOL_VERSION,
+            deadline: None,
+            wait_online: None,
+            wait_interval: Duration::from_secs(1),
+            only_latency: false,
+            unix_socket: None,
+            replay: None,
+            no_ping: false,
+            ping_payload: None,
+            tcp_check: false,
+            fail_on_empty: false,
+            fail_if_full: false,
+            expect_version: None,
+            expect_protocol: None,
+            match_protocol: false,
+            protocol_fallback: false,
+            fail_on_mismatch_only: false,
+            bind_port: None,
+            buffer_size: None,
+            lan_interface: Ipv4Addr::UNSPECIFIED,
+            timestamps: false,
+            once: false,
+            output: None,
+            append: false,
+            obfuscate_mode: ObfuscateMode::Blink,
+            format: None,
+            compact: false,
+            named_protocol: false,
+            describe: false,
+            timing: false,
+            early_hints: false,
+            motd_width: 40,
+            motd_lines: None,
+            clean_motd: false,
+            login_probe: false,
+            username: "mping".to_owned(),
+            brand: None,
+            probe_protocols: vec![],
+            scan_motd: None,
+        }
+    }
+}
+
+impl CommandLineArguments {
+    pub fn parse<T: Iterator<Item = String>>(args: &mut T) -> Result<Self, String> {
+        Self::parse_with_env(args, |name| std::env::var(name).ok())
+    }
+
+    // Same as parse, but environment variables are looked up with the given function so tests don't depend on the
+    // real environment
+    fn parse_with_env<T: Iterator<Item = String>, E: Fn(&str) -> Option<String>>(
+        args: &mut T,
+        env: E,
+    ) -> Result<Self, String> {
+        let mut arguments = CommandLineArguments::default();
+        let mut protocol_set = false;
+        let mut mc_version_set = false;
+        let mut username_set = false;
+        let mut wait_interval_set = false;
 
         // Skip executable name
         let mut args = args.skip(1).peekable();
@@ -37,9 +229,209 @@ impl CommandLineArguments {
                 let flag = flags_iter.next().ok_or(String::from("Invalid flags"))?;
                 match flag.as_ref() {
                     "-v" | "--verbose" => arguments.verbose = true,
+                    "-q" | "--quiet" => arguments.quiet = true,
+                    "--no-color" => arguments.no_color = true,
+                    "--list-colors" => arguments.list_colors = true,
+                    "--dump-packets" => arguments.dump_packets = true,
+                    "--capture" => {
+                        let path = next_flag_value(flags_iter, &flag)?;
+                        arguments.capture = Some(PathBuf::from(path));
+                    }
+                    "--timestamps" => arguments.timestamps = true,
+                    "--once" => arguments.once = true,
                     "-f" | "--favicon" => arguments.get_favicon = true,
+                    "--favicon-hash" => arguments.favicon_hash = true,
                     "-r" | "--raw-response" => arguments.raw_response = true,
+                    "--pretty" => arguments.pretty = true,
                     "-l" | "--lan" => arguments.open_to_lan = true,
+                    "--raw-description" => arguments.raw_description = true,
+                    "--lossy" => arguments.lossy = true,
+                    "--max-packet-size" => {
+                        let size = next_flag_value(flags_iter, &flag)?;
+                        arguments.max_packet_size = size
+                            .parse()
+                            .ok()
+                            .filter(|size| *size > 0)
+                            .ok_or(format!("Invalid maximum packet size \'{size}\'"))?;
+                    }
+                    "--latency" => arguments.only_latency = true,
+                    "--timing" => arguments.timing = true,
+                    "--early-hints" => arguments.early_hints = true,
+                    "--no-ping" => arguments.no_ping = true,
+                    "--ping-payload" => {
+                        let payload = next_flag_value(flags_iter, &flag)?;
+                        arguments.ping_payload = Some(parse_ping_payload(&payload)?);
+                    }
+                    "--tcp-check" => arguments.tcp_check = true,
+                    "-4" | "--ipv4" | "-6" | "--ipv6" => {
+                        let family = if flag.contains('4') {
+                            AddressFamily::Ipv4
+                        } else {
+                            AddressFamily::Ipv6
+                        };
+                        if arguments.address_family != AddressFamily::Any
+                            && arguments.address_family != family
+                        {
+                            return Err("--ipv4 is incompatible with --ipv6".to_owned());
+                        }
+                        arguments.address_family = family;
+                    }
+                    "--fail-on-empty" => arguments.fail_on_empty = true,
+                    "--fail-if-full" => arguments.fail_if_full = true,
+                    "--match-protocol" => arguments.match_protocol = true,
+                    "--protocol-fallback" => arguments.protocol_fallback = true,
+                    "--fail-on-mismatch-only" => arguments.fail_on_mismatch_only = true,
+                    "--expect-version" => {
+                        arguments.expect_version = Some(next_flag_value(flags_iter, &flag)?)
+                    }
+                    "--expect-protocol" => {
+                        let protocol = next_flag_value(flags_iter, &flag)?;
+                        arguments.expect_protocol = Some(
+                            protocol
+                                .parse()
+                                .map_err(|_| format!("Invalid protocol number '{protocol}'"))?,
+                        );
+                    }
+                    "--append" => arguments.append = true,
+                    "--obfuscate-mode" => {
+                        let mode = next_flag_value(flags_iter, &flag)?;
+                        arguments.obfuscate_mode = match mode.as_ref() {
+                            "blink" => ObfuscateMode::Blink,
+                            "blocks" => ObfuscateMode::Blocks,
+                            "hide" => ObfuscateMode::Hide,
+                            "plain" => ObfuscateMode::Plain,
+                            _ => return Err(format!("Invalid obfuscate mode \'{mode}\'")),
+                        };
+                    }
+                    "--compact" => arguments.compact = true,
+                    "--clean-motd" => arguments.clean_motd = true,
+                    "--named-protocol" => arguments.named_protocol = true,
+                    "--describe" => arguments.describe = true,
+                    "--motd-width" => {
+                        let width = next_flag_value(flags_iter, &flag)?;
+                        arguments.motd_width = width
+                            .parse()
+                            .map_err(|_| format!("Invalid MOTD width \'{width}\'"))?;
+                    }
+                    "--motd-lines" => {
+                        let lines = next_flag_value(flags_iter, &flag)?;
+                        arguments.motd_lines =
+                            Some(lines.parse().map_err(|_| {
+                                format!("Invalid number of MOTD lines \'{lines}\'")
+                            })?);
+                    }
+                    "--login-probe" => arguments.login_probe = true,
+                    "--username" => {
+                        let username = next_flag_value(flags_iter, &flag)?;
+                        // Vanilla servers reject empty names and names longer than 16 characters
+                        if username.is_empty() || username.chars().count() > 16 {
+                            return Err(format!("Invalid username \'{username}\'"));
+                        }
+                        arguments.username = username;
+                        username_set = true;
+                    }
+                    "--brand" => arguments.brand = Some(next_flag_value(flags_iter, &flag)?),
+                    "--format" => arguments.format = Some(next_flag_value(flags_iter, &flag)?),
+                    "--output" => {
+                        let path = next_flag_value(flags_iter, &flag)?;
+                        arguments.output = Some(PathBuf::from(path));
+                    }
+                    "--protocol" => {
+                        let protocol = next_flag_value(flags_iter, &flag)?;
+                        arguments.protocol = protocol
+                            .parse()
+                            .map_err(|_| format!("Invalid protocol number \'{protocol}\'"))?;
+                        protocol_set = true;
+                    }
+                    "--probe-protocols" => {
+                        let protocols = next_flag_value(flags_iter, &flag)?;
+                        arguments.probe_protocols = protocols
+                            .split(',')
+                            .map(|protocol| {
+                                protocol
+                                    .trim()
+                                    .parse()
+                                    .map_err(|_| format!("Invalid protocol number \'{protocol}\'"))
+                            })
+                            .collect::<Result<_, _>>()?;
+                    }
+                    "--scan-motd" => {
+                        let pings = next_flag_value(flags_iter, &flag)?;
+                        arguments.scan_motd = Some(
+                            pings
+                                .parse()
+                                .ok()
+                                .filter(|pings| *pings > 0)
+                                .ok_or(format!("Invalid number of pings '{pings}'"))?,
+                        );
+                    }
+                    "--mc-version" => {
+                        let version = next_flag_value(flags_iter, &flag)?;
+                        arguments.protocol = versions::protocol_for_version(&version).ok_or(format!(
+                            "Unknown Minecraft version \'{version}\'. Use --protocol to provide the protocol number directly"
+                        ))?;
+                        mc_version_set = true;
+                    }
+                    "--deadline" => {
+                        let seconds = next_flag_value(flags_iter, &flag)?;
+                        arguments.deadline = Some(parse_seconds(&seconds, "deadline")?);
+                    }
+                    "--wait-online" => {
+                        let seconds = next_flag_value(flags_iter, &flag)?;
+                        arguments.wait_online = Some(parse_seconds(&seconds, "wait timeout")?);
+                    }
+                    "--wait-interval" => {
+                        let seconds = next_flag_value(flags_iter, &flag)?;
+                        arguments.wait_interval = parse_seconds(&seconds, "wait interval")?;
+                        wait_interval_set = true;
+                    }
+                    "--bind-port" => {
+                        let port = next_flag_value(flags_iter, &flag)?;
+                        arguments.bind_port = Some(
+                            port.parse()
+                                .map_err(|_| format!("Invalid bind port \'{port}\'"))?,
+                        );
+                    }
+                    "--buffer-size" => {
+                        let size = next_flag_value(flags_iter, &flag)?;
+                        // No UDP datagram is bigger than 64 KiB
+                        arguments.buffer_size = Some(
+                            size.parse()
+                                .ok()
+                                .filter(|size| (1..=65536).contains(size))
+                                .ok_or(format!("Invalid buffer size '{size}'"))?,
+                        );
+                    }
+                    "--lan-interface" => {
+                        let interface = next_flag_value(flags_iter, &flag)?;
+                        arguments.lan_interface = interface.parse().map_err(|_| {
+                            format!("Invalid IPv4 address for the LAN interface '{interface}'")
+                        })?;
+                    }
+                    "--replay" => {
+                        let path = next_flag_value(flags_iter, &flag)?;
+                        arguments.replay = Some(PathBuf::from(path));
+                    }
+                    "--forge" => {
+                        let marker = next_flag_value(flags_iter, &flag)?;
+                        arguments.forge = match marker.as_ref() {
+                            "fml" => Some(ForgeMarker::Fml),
+                            "fml2" => Some(ForgeMarker::Fml2),
+                            _ => {
+                                return Err(format!(
+                                    "Invalid Forge marker \'{marker}\'. Use fml or fml2"
+                                ))
+                            }
+                        };
+                    }
+                    "--handshake-host" => {
+                        let host = next_flag_value(flags_iter, &flag)?;
+                        arguments.handshake_host = Some(unescape_nulls(&host)?);
+                    }
+                    "--unix" => {
+                        let path = next_flag_value(flags_iter, &flag)?;
+                        arguments.unix_socket = Some(PathBuf::from(path));
+                    }
                     _ => return Err(format!("Unrecognized flag: {flag}")),
                 }
             } else {
@@ -48,23 +440,314 @@ impl CommandLineArguments {
             }
         }
 
+        if arguments.verbose && arguments.quiet {
+            return Err("-v is incompatible with -q".to_owned());
+        }
+
+        if arguments.append && arguments.output.is_none() {
+            return Err("--append can only be used with --output".to_owned());
+        }
+
+        if protocol_set && mc_version_set {
+            return Err("--protocol is incompatible with --mc-version".to_owned());
+        }
+
+        if arguments.match_protocol && (protocol_set || mc_version_set) {
+            return Err(
+                "--match-protocol is incompatible with --protocol and --mc-version".to_owned(),
+            );
+        }
+        if arguments.protocol_fallback && (protocol_set || mc_version_set) {
+            return Err(
+                "--protocol-fallback is incompatible with --protocol and --mc-version".to_owned(),
+            );
+        }
+
+        if !arguments.probe_protocols.is_empty() && (protocol_set || mc_version_set) {
+            return Err(
+                "--probe-protocols is incompatible with --protocol and --mc-version".to_owned(),
+            );
+        }
+
+        if arguments.favicon_hash && !arguments.get_favicon {
+            return Err("--favicon-hash can only be used with -f".to_owned());
+        }
+        if arguments.favicon_hash && arguments.raw_response {
+            return Err("--favicon-hash is incompatible with -r".to_owned());
+        }
+
+        if arguments.pretty && !arguments.raw_response {
+            return Err("--pretty can only be used with -r".to_owned());
+        }
+        if arguments.pretty && arguments.get_favicon {
+            return Err("--pretty is incompatible with -f".to_owned());
+        }
+
+        if wait_interval_set && arguments.wait_online.is_none() {
+            return Err("--wait-interval can only be used with --wait-online".to_owned());
+        }
+        if arguments.wait_online.is_some() && arguments.login_probe {
+            return Err("--wait-online is incompatible with --login-probe".to_owned());
+        }
+        if arguments.wait_online.is_some() && !arguments.probe_protocols.is_empty() {
+            return Err("--wait-online is incompatible with --probe-protocols".to_owned());
+        }
+        if arguments.wait_online.is_some() && arguments.scan_motd.is_some() {
+            return Err("--wait-online is incompatible with --scan-motd".to_owned());
+        }
+        if arguments.wait_online.is_some() && arguments.tcp_check {
+            return Err("--wait-online is incompatible with --tcp-check".to_owned());
+        }
+
+        if arguments.clean_motd && !arguments.compact && arguments.format.is_none() {
+            return Err("--clean-motd can only be used with --compact or --format".to_owned());
+        }
+
+        if username_set && !arguments.login_probe {
+            return Err("--username can only be used with --login-probe".to_owned());
+        }
+        if arguments.brand.is_some() && !arguments.login_probe {
+            return Err("--brand can only be used with --login-probe".to_owned());
+        }
+
+        // Servers refuse to log in outdated clients before looking at the username, so the probe needs a real protocol
+        if arguments.login_probe && !protocol_set && !mc_version_set {
+            arguments.protocol = versions::latest_protocol_version();
+        }
+
+        if arguments.list_colors {
+            // Diagnostic mode. It doesn't talk to any server, so there's no address to parse
+            if arguments.open_to_lan {
+                return Err("--list-colors is incompatible with -l".to_owned());
+            }
+            if args.count() != 0 {
+                return Err("Invalid arguments".to_owned());
+            }
+            return Ok(arguments);
+        }
+
         if arguments.open_to_lan {
             // Open to LAN mode. Host and port not needed.
-            if arguments.get_favicon {
-                return Err("-f is incompatible with -l".to_owned());
+            // Flags that only make sense when pinging a server
+            let ping_flags = [
+                ("-f", arguments.get_favicon),
+                ("--pretty", arguments.pretty),
+                ("--latency", arguments.only_latency),
+                ("--unix", arguments.unix_socket.is_some()),
+                ("--replay", arguments.replay.is_some()),
+                ("--ping-payload", arguments.ping_payload.is_some()),
+                ("--handshake-host", arguments.handshake_host.is_some()),
+                ("--forge", arguments.forge.is_some()),
+                (
+                    "--ipv4/--ipv6",
+                    arguments.address_family != AddressFamily::Any,
+                ),
+                ("--no-ping", arguments.no_ping),
+                ("--format", arguments.format.is_some()),
+                ("--compact", arguments.compact),
+                ("--named-protocol", arguments.named_protocol),
+                ("--describe", arguments.describe),
+                ("--timing", arguments.timing),
+                ("--early-hints", arguments.early_hints),
+                ("--login-probe", arguments.login_probe),
+                ("--probe-protocols", !arguments.probe_protocols.is_empty()),
+                ("--wait-online", arguments.wait_online.is_some()),
+                ("--tcp-check", arguments.tcp_check),
+                ("--scan-motd", arguments.scan_motd.is_some()),
+                ("--fail-on-empty", arguments.fail_on_empty),
+                ("--fail-if-full", arguments.fail_if_full),
+                ("--expect-version", arguments.expect_version.is_some()),
+                ("--expect-protocol", arguments.expect_protocol.is_some()),
+                ("--match-protocol", arguments.match_protocol),
+                ("--protocol-fallback", arguments.protocol_fallback),
+                ("--fail-on-mismatch-only", arguments.fail_on_mismatch_only),
+                ("--dump-packets", arguments.dump_packets),
+                ("--capture", arguments.capture.is_some()),
+                ("--raw-description", arguments.raw_description),
+                (
+                    "--max-packet-size",
+                    arguments.max_packet_size != DEFAULT_MAX_PACKET_SIZE,
+                ),
+            ];
+            if let Some((flag, _)) = ping_flags.iter().find(|(_, used)| *used) {
+                return Err(format!("{flag} is incompatible with -l"));
             }
         } else {
             // Normal mode. Parse address as a required argument.
-            match args.next() {
-                Some(host) => arguments.host = host,
-                None => return Err("No address provided".to_owned()),
+
+            // Only one way of printing the status can be used at once, with the exception of -f and -r which can be
+            // combined to print the favicon without decoding it
+            let output_flags = [
+                ("-f", arguments.get_favicon),
+                ("-r", arguments.raw_response),
+                ("--latency", arguments.only_latency),
+                ("--format", arguments.format.is_some()),
+                ("--compact", arguments.compact),
+                ("--login-probe", arguments.login_probe),
+                ("--probe-protocols", !arguments.probe_protocols.is_empty()),
+                ("--raw-description", arguments.raw_description),
+                ("--tcp-check", arguments.tcp_check),
+                ("--scan-motd", arguments.scan_motd.is_some()),
+            ];
+            // The breakdown is printed below the status table
+            if let Some((flag, _)) = output_flags
+                .iter()
+                .find(|(_, used)| arguments.timing && *used)
+            {
+                return Err(format!("--timing is incompatible with {flag}"));
+            }
+            // The status is printed before the ping, so it can't wait for a second attempt or the ping timing
+            let early_hints_conflicts = [
+                ("--timing", arguments.timing),
+                ("--wait-online", arguments.wait_online.is_some()),
+                ("--match-protocol", arguments.match_protocol),
+                ("--protocol-fallback", arguments.protocol_fallback),
+            ];
+            if let Some((flag, _)) = output_flags
+                .iter()
+                .chain(&early_hints_conflicts)
+                .find(|(_, used)| arguments.early_hints && *used)
+            {
+                return Err(format!("--early-hints is incompatible with {flag}"));
+            }
+            if arguments.capture.is_some() && arguments.tcp_check {
+                return Err("--capture is incompatible with --tcp-check".to_owned());
+            }
+            for (i, (flag, used)) in output_flags.iter().enumerate() {
+                for (other_flag, other_used) in &output_flags[i + 1..] {
+                    if *used && *other_used && !(*flag == "-f" && *other_flag == "-r") {
+                        return Err(format!("{flag} is incompatible with {other_flag}"));
+                    }
+                }
+            }
+            if arguments.login_probe && arguments.no_ping {
+                return Err("--login-probe is incompatible with --no-ping".to_owned());
+            }
+            // These only apply to a regular status ping
+            let status_checks = [
+                ("--fail-on-empty", arguments.fail_on_empty),
+                ("--fail-if-full", arguments.fail_if_full),
+                ("--expect-version", arguments.expect_version.is_some()),
+                ("--expect-protocol", arguments.expect_protocol.is_some()),
+                ("--match-protocol", arguments.match_protocol),
+                ("--protocol-fallback", arguments.protocol_fallback),
+                ("--fail-on-mismatch-only", arguments.fail_on_mismatch_only),
+            ];
+            let other_modes = [
+                ("--login-probe", arguments.login_probe),
+                ("--probe-protocols", !arguments.probe_protocols.is_empty()),
+                ("--tcp-check", arguments.tcp_check),
+                ("--scan-motd", arguments.scan_motd.is_some()),
+            ];
+            for (flag, used) in status_checks {
+                if let Some((mode, _)) =
+                    other_modes.iter().find(|(_, mode_used)| used && *mode_used)
+                {
+                    return Err(format!("{flag} is incompatible with {mode}"));
+                }
+            }
+            // A replay reads the server's answers from a file, so there's no connection to time or wait for
+            let replay_conflicts = [
+                ("--unix", arguments.unix_socket.is_some()),
+                (
+                    "--ipv4/--ipv6",
+                    arguments.address_family != AddressFamily::Any,
+                ),
+                ("--latency", arguments.only_latency),
+                ("--tcp-check", arguments.tcp_check),
+                ("--wait-online", arguments.wait_online.is_some()),
+            ];
+            if let Some((flag, _)) = replay_conflicts
+                .iter()
+                .find(|(_, used)| arguments.replay.is_some() && *used)
+            {
+                return Err(format!("--replay is incompatible with {flag}"));
+            }
+            // Only the pong decides the exit code, so there must be a pong and nothing else to check
+            if arguments.fail_on_mismatch_only {
+                if arguments.no_ping || arguments.replay.is_some() {
+                    return Err(
+                        "--fail-on-mismatch-only is incompatible with --no-ping and --replay"
+                            .to_owned(),
+                    );
+                }
+                let exit_code_checks = [
+                    ("--fail-on-empty", arguments.fail_on_empty),
+                    ("--fail-if-full", arguments.fail_if_full),
+                    ("--expect-version", arguments.expect_version.is_some()),
+                    ("--expect-protocol", arguments.expect_protocol.is_some()),
+                ];
+                if let Some((flag, _)) = exit_code_checks.iter().find(|(_, used)| *used) {
+                    return Err(format!(
+                        "--fail-on-mismatch-only is incompatible with {flag}"
+                    ));
+                }
+            }
+            if arguments.ping_payload.is_some() && (arguments.no_ping || arguments.replay.is_some())
+            {
+                return Err("--ping-payload is incompatible with --no-ping and --replay".to_owned());
+            }
+            if arguments.unix_socket.is_some() && arguments.address_family != AddressFamily::Any {
+                return Err("--unix is incompatible with --ipv4/--ipv6".to_owned());
+            }
+            if arguments.tcp_check && arguments.no_ping {
+                return Err("--tcp-check is incompatible with --no-ping".to_owned());
+            }
+            if arguments.only_latency && arguments.no_ping {
+                return Err("--latency is incompatible with --no-ping".to_owned());
+            }
+            if arguments.bind_port.is_some() {
+                return Err("--bind-port can only be used with -l".to_owned());
+            }
+            if arguments.buffer_size.is_some() {
+                return Err("--buffer-size can only be used with -l".to_owned());
+            }
+            if !arguments.lan_interface.is_unspecified() {
+                return Err("--lan-interface can only be used with -l".to_owned());
+            }
+            if arguments.timestamps {
+                return Err("--timestamps can only be used with -l".to_owned());
+            }
+            if arguments.once {
+                return Err("--once can only be used with -l".to_owned());
+            }
+
+            let combined_port = match args.next() {
+                Some(address) => {
+                    let (host, port) = split_host_and_port(&address)?;
+                    arguments.host = host;
+                    port
+                }
+                // The replayed bytes don't depend on the address, so it's optional
+                None if arguments.replay.is_some() => None,
+                None => match env("MCPING_HOST") {
+                    // Fall back to the server the user monitors the most
+                    Some(address) => {
+                        let (host, port) = split_host_and_port(&address)?;
+                        arguments.host = host;
+                        match (port, env("MCPING_DEFAULT_PORT")) {
+                            (None, Some(port)) => Some(
+                                parse_port(&port)
+                                    .map_err(|e| format!("{e} in MCPING_DEFAULT_PORT"))?,
+                            ),
+                            (port, _) => port,
+                        }
+                    }
+                    None => return Err("No address provided".to_owned()),
+                },
+            };
+            if let Some(port) = combined_port {
+                arguments.port = port;
             }
 
             // Parse port as an optional argument
             if let Some(port) = args.next() {
-                arguments.port = port
-                    .parse()
-                    .map_err(|_| format!("Invalid port \'{port}\'"))?;
+                if combined_port.is_some() {
+                    return Err(format!(
+                        "The port was already given in the address. Unexpected port \'{port}\'"
+                    ));
+                }
+                arguments.port = parse_port(&port)?;
             }
         }
 
@@ -77,6 +760,88 @@ impl CommandLineArguments {
     }
 }
 
+fn split_host_and_port(address: &str) -> Result<(String, Option<u16>), String> {
+    // Accepts "host", "host:port", "[ipv6]" and "[ipv6]:port". A bare IPv6 address has several colons, so it's taken as
+    // a host without a port.
+    if let Some((_, rest)) = address.split_once("://") {
+        // Easy mistake when copying the address from a browser
+        let suggestion = rest.split('/').next().unwrap_or(rest);
+        return Err(format!(
+            "\'{address}\' looks like a URL. Pass just the hostname instead, like \'{suggestion}\'"
+        ));
+    }
+
+    if let Some(rest) = address.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or(format!("Invalid address \'{address}\'"))?;
+        return match rest {
+            "" => Ok((host.to_owned(), None)),
+            _ => match rest.strip_prefix(':') {
+                Some(port) => Ok((host.to_owned(), Some(parse_port(port)?))),
+                None => Err(format!("Invalid address \'{address}\'")),
+            },
+        };
+    }
+
+    match address.split_once(':') {
+        Some((host, port)) if !port.contains(':') => Ok((host.to_owned(), Some(parse_port(port)?))),
+        _ => Ok((address.to_owned(), None)),
+    }
+}
+
+fn parse_seconds(seconds: &str, name: &str) -> Result<Duration, String> {
+    // A positive amount of seconds, fractions allowed
+    seconds
+        .parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .filter(|duration| !duration.is_zero())
+        .ok_or(format!("Invalid {name} \'{seconds}\'"))
+}
+
+fn unescape_nulls(value: &str) -> Result<String, String> {
+    // Command line arguments can't contain null bytes, so they are written as \0. A literal backslash is \\.
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('0') => unescaped.push('\0'),
+                Some('\\') => unescaped.push('\\'),
+                _ => {
+                    return Err(format!(
+                        "Invalid escape sequence in \'{value}\'. Use \\0 or \\\\"
+                    ))
+                }
+            },
+            c => unescaped.push(c),
+        }
+    }
+    Ok(unescaped)
+}
+
+fn parse_ping_payload(payload: &str) -> Result<i64, String> {
+    // A signed decimal number, or any 64-bit pattern in hexadecimal such as 0xffffffffffffffff
+    let parsed = match payload
+        .strip_prefix("0x")
+        .or_else(|| payload.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).map(|value| value as i64).ok(),
+        None => payload.parse().ok(),
+    };
+    parsed.ok_or(format!("Invalid ping payload '{payload}'"))
+}
+
+fn parse_port(port: &str) -> Result<u16, String> {
+    port.parse().map_err(|_| format!("Invalid port \'{port}\'"))
+}
+
+fn next_flag_value<T: Iterator<Item = String>>(args: &mut T, flag: &str) -> Result<String, String> {
+    // Flags that take a value consume the next argument
+    args.next().ok_or(format!("Missing value for flag {flag}"))
+}
+
 #[cfg(test)]
 mod cli_arguments_tests {
     use super::*;
@@ -84,7 +849,7 @@ mod cli_arguments_tests {
     #[test]
     fn test_parse_when_no_arguments_given() {
         let cli_args = [String::from("./command")];
-        let args = CommandLineArguments::parse(&mut cli_args.into_iter());
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
 
         assert!(args.is_err());
     }
@@ -92,7 +857,7 @@ mod cli_arguments_tests {
     #[test]
     fn test_parse_address() {
         let cli_args = [String::from("./command"), String::from("127.0.0.1")];
-        let args = CommandLineArguments::parse(&mut cli_args.into_iter());
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
         let expected = Ok(CommandLineArguments {
             get_favicon: false,
             raw_response: false,
@@ -100,6 +865,7 @@ mod cli_arguments_tests {
             open_to_lan: false,
             host: "127.0.0.1".to_owned(),
             port: 25565,
+            ..CommandLineArguments::default()
         });
         assert_eq!(expected, args);
     }
@@ -111,7 +877,7 @@ mod cli_arguments_tests {
             String::from("127.0.0.1"),
             String::from("25560"),
         ];
-        let args = CommandLineArguments::parse(&mut cli_args.into_iter());
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
         let expected = Ok(CommandLineArguments {
             get_favicon: false,
             raw_response: false,
@@ -119,6 +885,7 @@ mod cli_arguments_tests {
             open_to_lan: false,
             host: "127.0.0.1".to_owned(),
             port: 25560,
+            ..CommandLineArguments::default()
         });
         assert_eq!(expected, args);
     }
@@ -130,7 +897,7 @@ mod cli_arguments_tests {
             String::from("--unrecognized-flag"),
             String::from("localhost"),
         ];
-        let args = CommandLineArguments::parse(&mut cli_args.into_iter());
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
         assert!(args.is_err());
     }
 
@@ -141,7 +908,7 @@ mod cli_arguments_tests {
             String::from("-v"),
             String::from("localhost"),
         ];
-        let args = CommandLineArguments::parse(&mut cli_args.into_iter());
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
         let expected = Ok(CommandLineArguments {
             get_favicon: false,
             raw_response: false,
@@ -149,6 +916,7 @@ mod cli_arguments_tests {
             open_to_lan: false,
             host: "localhost".to_owned(),
             port: 25565,
+            ..CommandLineArguments::default()
         });
         assert_eq!(expected, args);
     }
@@ -161,7 +929,7 @@ mod cli_arguments_tests {
             String::from("localhost"),
             String::from("1000"),
         ];
-        let args = CommandLineArguments::parse(&mut cli_args.into_iter());
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
         let expected = Ok(CommandLineArguments {
             get_favicon: false,
             raw_response: false,
@@ -169,6 +937,7 @@ mod cli_arguments_tests {
             open_to_lan: false,
             host: "localhost".to_owned(),
             port: 1000,
+            ..CommandLineArguments::default()
         });
         assert_eq!(expected, args);
     }
@@ -181,7 +950,7 @@ mod cli_arguments_tests {
             String::from("-v"),
             String::from("1000"),
         ];
-        let args = CommandLineArguments::parse(&mut cli_args.into_iter());
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
         assert!(args.is_err());
     }
 
@@ -193,7 +962,1565 @@ mod cli_arguments_tests {
             String::from("1000"),
             String::from("-v"),
         ];
-        let args = CommandLineArguments::parse(&mut cli_args.into_iter());
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_protocol_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--protocol"),
+            String::from("765"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            protocol: 765,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_mc_version_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--mc-version"),
+            String::from("1.20.4"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            protocol: 765,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_unknown_mc_version() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--mc-version"),
+            String::from("1.99"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.unwrap_err().contains("--protocol"));
+    }
+
+    #[test]
+    fn test_parse_protocol_and_mc_version_flags() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--protocol"),
+            String::from("765"),
+            String::from("--mc-version"),
+            String::from("1.20.4"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_deadline_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--deadline"),
+            String::from("2.5"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            deadline: Some(Duration::from_millis(2500)),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_invalid_deadline() {
+        for deadline in ["0", "-1", "soon"] {
+            let cli_args = [
+                String::from("./command"),
+                String::from("--deadline"),
+                String::from(deadline),
+                String::from("localhost"),
+            ];
+            let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+            assert!(args.is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_latency_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--latency"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            only_latency: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_latency_flag_with_incompatible_flags() {
+        for flag in ["-f", "-r", "-l"] {
+            let cli_args = [
+                String::from("./command"),
+                String::from("--latency"),
+                String::from(flag),
+                String::from("localhost"),
+            ];
+            let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+            assert!(args.is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_unix_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--unix"),
+            String::from("/tmp/proxy.sock"),
+            String::from("example.com"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "example.com".to_owned(),
+            unix_socket: Some(PathBuf::from("/tmp/proxy.sock")),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_no_ping_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--no-ping"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            no_ping: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_no_ping_and_latency_flags() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--no-ping"),
+            String::from("--latency"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_bind_port_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-l"),
+            String::from("--bind-port"),
+            String::from("4446"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            open_to_lan: true,
+            bind_port: Some(4446),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_out_of_range_bind_port() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-l"),
+            String::from("--bind-port"),
+            String::from("65536"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_output_flags() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--output"),
+            String::from("status.txt"),
+            String::from("--append"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            output: Some(PathBuf::from("status.txt")),
+            append: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_append_without_output() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--append"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
         assert!(args.is_err());
     }
+
+    #[test]
+    fn test_parse_obfuscate_mode_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--obfuscate-mode"),
+            String::from("blocks"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            obfuscate_mode: ObfuscateMode::Blocks,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_invalid_obfuscate_mode() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--obfuscate-mode"),
+            String::from("sparkle"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_format_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--format"),
+            String::from("{online}/{max}"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            format: Some("{online}/{max}".to_owned()),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_compact_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--compact"),
+            String::from("--motd-width"),
+            String::from("20"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            compact: true,
+            motd_width: 20,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_compact_flag_with_incompatible_flags() {
+        for flag in ["-f", "-r", "--latency", "-l"] {
+            let cli_args = [
+                String::from("./command"),
+                String::from("--compact"),
+                String::from(flag),
+                String::from("localhost"),
+            ];
+            let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+            assert!(args.is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_favicon_and_raw_response_flags() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-f"),
+            String::from("-r"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            get_favicon: true,
+            raw_response: true,
+            host: "localhost".to_owned(),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_motd_lines_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--motd-lines"),
+            String::from("1"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            motd_lines: Some(1),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_login_probe_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--login-probe"),
+            String::from("--username"),
+            String::from("Notch"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            login_probe: true,
+            username: "Notch".to_owned(),
+            protocol: versions::latest_protocol_version(),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_login_probe_flag_keeps_requested_version() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--login-probe"),
+            String::from("--mc-version"),
+            String::from("1.8.9"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            login_probe: true,
+            protocol: 47,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_username_without_login_probe() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--username"),
+            String::from("Notch"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_username_too_long() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--login-probe"),
+            String::from("--username"),
+            String::from("ThisNameIsWayTooLong"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_favicon_hash_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-f"),
+            String::from("--favicon-hash"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            get_favicon: true,
+            favicon_hash: true,
+            host: "localhost".to_owned(),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_favicon_hash_without_favicon_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--favicon-hash"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_probe_protocols_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--probe-protocols"),
+            String::from("47,340, 765"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            probe_protocols: vec![47, 340, 765],
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_probe_protocols_invalid_number() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--probe-protocols"),
+            String::from("47,abc"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(args, Err("Invalid protocol number \'abc\'".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_no_color_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--no-color"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            no_color: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_address_with_port() {
+        let cli_args = [String::from("./command"), String::from("example.com:25560")];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "example.com".to_owned(),
+            port: 25560,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_address_without_port() {
+        let cli_args = [String::from("./command"), String::from("example.com")];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "example.com".to_owned(),
+            port: 25565,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_address_with_port_and_separate_port() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("example.com:25560"),
+            String::from("25561"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_bracketed_ipv6_address_with_port() {
+        let cli_args = [String::from("./command"), String::from("[::1]:25560")];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "::1".to_owned(),
+            port: 25560,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_bare_ipv6_address() {
+        let cli_args = [String::from("./command"), String::from("::1")];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "::1".to_owned(),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_address_with_invalid_port() {
+        let cli_args = [String::from("./command"), String::from("example.com:abc")];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(args, Err("Invalid port \'abc\'".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_wait_online_flags() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--wait-online"),
+            String::from("60"),
+            String::from("--wait-interval"),
+            String::from("0.5"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            wait_online: Some(Duration::from_secs(60)),
+            wait_interval: Duration::from_millis(500),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_wait_interval_without_wait_online() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--wait-interval"),
+            String::from("2"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_brand_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--login-probe"),
+            String::from("--brand"),
+            String::from("vanilla"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            login_probe: true,
+            brand: Some("vanilla".to_owned()),
+            protocol: versions::latest_protocol_version(),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_brand_without_login_probe() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--brand"),
+            String::from("vanilla"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_timestamps_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-l"),
+            String::from("--timestamps"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            open_to_lan: true,
+            timestamps: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_timestamps_without_lan() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--timestamps"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_dump_packets_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--dump-packets"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            dump_packets: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    fn fake_env(name: &str) -> Option<String> {
+        match name {
+            "MCPING_HOST" => Some("example.com".to_owned()),
+            "MCPING_DEFAULT_PORT" => Some("25570".to_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_parse_host_from_env() {
+        let cli_args = [String::from("./command")];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), fake_env);
+        let expected = Ok(CommandLineArguments {
+            host: "example.com".to_owned(),
+            port: 25570,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_host_overrides_env() {
+        let cli_args = [String::from("./command"), String::from("localhost")];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), fake_env);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_without_host_or_env() {
+        let cli_args = [String::from("./command")];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(args, Err("No address provided".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_host_from_env_with_invalid_port() {
+        let cli_args = [String::from("./command")];
+        let env = |name: &str| match name {
+            "MCPING_HOST" => Some("example.com".to_owned()),
+            "MCPING_DEFAULT_PORT" => Some("abc".to_owned()),
+            _ => None,
+        };
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), env);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_http_url() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("http://example.com"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(
+            args,
+            Err("\'http://example.com\' looks like a URL. Pass just the hostname instead, like \'example.com\'".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_https_url_with_port() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("https://example.com:25565/"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(
+            args,
+            Err("\'https://example.com:25565/\' looks like a URL. Pass just the hostname instead, like \'example.com:25565\'".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_raw_description_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--raw-description"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            raw_description: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_raw_description_with_favicon() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-f"),
+            String::from("--raw-description"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(
+            args,
+            Err("-f is incompatible with --raw-description".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_lossy_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--lossy"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            lossy: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_max_packet_size_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--max-packet-size"),
+            String::from("1024"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            max_packet_size: 1024,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_once_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-l"),
+            String::from("--once"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            open_to_lan: true,
+            once: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_once_without_lan() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--once"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(args, Err("--once can only be used with -l".to_owned()));
+    }
+
+    #[test]
+    fn test_color_policy_defaults_to_auto() {
+        assert_eq!(ColorPolicy::resolve(false, |_| None), ColorPolicy::Auto);
+    }
+
+    #[test]
+    fn test_color_policy_no_color_flag() {
+        assert_eq!(ColorPolicy::resolve(true, |_| None), ColorPolicy::Never);
+    }
+
+    #[test]
+    fn test_color_policy_no_color_env() {
+        let env = |name: &str| (name == "NO_COLOR").then(|| "1".to_owned());
+        assert_eq!(ColorPolicy::resolve(false, env), ColorPolicy::Never);
+    }
+
+    #[test]
+    fn test_color_policy_clicolor_force_env() {
+        let env = |name: &str| (name == "CLICOLOR_FORCE").then(|| "1".to_owned());
+        assert_eq!(ColorPolicy::resolve(false, env), ColorPolicy::Always);
+    }
+
+    #[test]
+    fn test_color_policy_no_color_flag_overrides_clicolor_force() {
+        let env = |name: &str| (name == "CLICOLOR_FORCE").then(|| "1".to_owned());
+        assert_eq!(ColorPolicy::resolve(true, env), ColorPolicy::Never);
+    }
+
+    #[test]
+    fn test_color_policy_no_color_env_overrides_clicolor_force() {
+        let env = |_: &str| Some("1".to_owned());
+        assert_eq!(ColorPolicy::resolve(false, env), ColorPolicy::Never);
+    }
+
+    #[test]
+    fn test_color_policy_ignores_values_other_than_1() {
+        let env = |_: &str| Some("0".to_owned());
+        assert_eq!(ColorPolicy::resolve(false, env), ColorPolicy::Auto);
+    }
+
+    #[test]
+    fn test_parse_tcp_check() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--tcp-check"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            tcp_check: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_tcp_check_with_raw_response() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--tcp-check"),
+            String::from("-r"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_pretty_raw_response() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-r"),
+            String::from("--pretty"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            raw_response: true,
+            pretty: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_pretty_without_raw_response() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--pretty"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(args, Err("--pretty can only be used with -r".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_pretty_with_lan() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-l"),
+            String::from("-r"),
+            String::from("--pretty"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(args, Err("--pretty is incompatible with -l".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_named_protocol() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--named-protocol"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            named_protocol: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_fail_on_empty_and_fail_if_full() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--fail-on-empty"),
+            String::from("--fail-if-full"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            fail_on_empty: true,
+            fail_if_full: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_fail_on_empty_with_tcp_check() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--fail-on-empty"),
+            String::from("--tcp-check"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(
+            args,
+            Err("--fail-on-empty is incompatible with --tcp-check".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_scan_motd() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--scan-motd"),
+            String::from("10"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            scan_motd: Some(10),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_scan_motd_zero_pings() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--scan-motd"),
+            String::from("0"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(args, Err("Invalid number of pings '0'".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_lan_interface() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-l"),
+            String::from("--lan-interface"),
+            String::from("192.168.1.20"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            open_to_lan: true,
+            lan_interface: Ipv4Addr::new(192, 168, 1, 20),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_lan_interface_ipv6() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-l"),
+            String::from("--lan-interface"),
+            String::from("::1"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_lan_interface_without_lan() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--lan-interface"),
+            String::from("192.168.1.20"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(
+            args,
+            Err("--lan-interface can only be used with -l".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_expect_version_and_protocol() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--expect-version"),
+            String::from("Paper 1.20.4"),
+            String::from("--expect-protocol"),
+            String::from("765"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            expect_version: Some("Paper 1.20.4".to_owned()),
+            expect_protocol: Some(765),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_invalid_expect_protocol() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--expect-protocol"),
+            String::from("1.20.4"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_match_protocol() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--match-protocol"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            match_protocol: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_match_protocol_with_protocol() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--match-protocol"),
+            String::from("--protocol"),
+            String::from("765"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_capture() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--capture"),
+            String::from("status.bin"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            capture: Some(PathBuf::from("status.bin")),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_replay_without_address() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--replay"),
+            String::from("status.bin"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            replay: Some(PathBuf::from("status.bin")),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_replay_with_latency() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--replay"),
+            String::from("status.bin"),
+            String::from("--latency"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(
+            args,
+            Err("--replay is incompatible with --latency".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_ping_payload_decimal() {
+        assert_eq!(parse_ping_payload("0"), Ok(0));
+        assert_eq!(parse_ping_payload("-42"), Ok(-42));
+        assert_eq!(parse_ping_payload("9223372036854775807"), Ok(i64::MAX));
+    }
+
+    #[test]
+    fn test_parse_ping_payload_hex() {
+        assert_eq!(parse_ping_payload("0x2a"), Ok(42));
+        assert_eq!(parse_ping_payload("0XFF"), Ok(255));
+        assert_eq!(parse_ping_payload("0xffffffffffffffff"), Ok(-1));
+        assert_eq!(parse_ping_payload("0x8000000000000000"), Ok(i64::MIN));
+    }
+
+    #[test]
+    fn test_parse_invalid_ping_payload() {
+        assert!(parse_ping_payload("").is_err());
+        assert!(parse_ping_payload("0x").is_err());
+        assert!(parse_ping_payload("0x1ffffffffffffffff").is_err());
+        assert!(parse_ping_payload("abc").is_err());
+    }
+
+    #[test]
+    fn test_parse_ping_payload_flag() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--ping-payload"),
+            String::from("-1"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            ping_payload: Some(-1),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_ipv4_and_ipv6_flags() {
+        for (flag, family) in [
+            ("-4", AddressFamily::Ipv4),
+            ("--ipv4", AddressFamily::Ipv4),
+            ("-6", AddressFamily::Ipv6),
+            ("--ipv6", AddressFamily::Ipv6),
+        ] {
+            let cli_args = [
+                String::from("./command"),
+                String::from(flag),
+                String::from("localhost"),
+            ];
+            let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+            let expected = Ok(CommandLineArguments {
+                host: "localhost".to_owned(),
+                address_family: family,
+                ..CommandLineArguments::default()
+            });
+            assert_eq!(expected, args, "{flag}");
+        }
+    }
+
+    #[test]
+    fn test_parse_ipv4_with_ipv6() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-4"),
+            String::from("-6"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(args, Err("--ipv4 is incompatible with --ipv6".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_describe() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--describe"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            describe: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_buffer_size() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-l"),
+            String::from("--buffer-size"),
+            String::from("8192"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            open_to_lan: true,
+            buffer_size: Some(8192),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_invalid_buffer_size() {
+        for size in ["0", "65537", "abc"] {
+            let cli_args = [
+                String::from("./command"),
+                String::from("-l"),
+                String::from("--buffer-size"),
+                String::from(size),
+            ];
+            let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+            assert!(args.is_err(), "{size}");
+        }
+    }
+
+    #[test]
+    fn test_unescape_nulls() {
+        assert_eq!(
+            unescape_nulls("play.example.com"),
+            Ok("play.example.com".to_owned())
+        );
+        assert_eq!(
+            unescape_nulls(r"example.com\0FML\0"),
+            Ok("example.com\0FML\0".to_owned())
+        );
+        assert_eq!(unescape_nulls(r"a\\0"), Ok(r"a\0".to_owned()));
+        assert!(unescape_nulls(r"a\n").is_err());
+        assert!(unescape_nulls(r"a\").is_err());
+    }
+
+    #[test]
+    fn test_parse_handshake_host() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--handshake-host"),
+            String::from(r"lobby.example.com\0FML\0"),
+            String::from("127.0.0.1"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "127.0.0.1".to_owned(),
+            handshake_host: Some("lobby.example.com\0FML\0".to_owned()),
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_forge() {
+        for (marker, expected_marker) in [("fml", ForgeMarker::Fml), ("fml2", ForgeMarker::Fml2)] {
+            let cli_args = [
+                String::from("./command"),
+                String::from("--forge"),
+                String::from(marker),
+                String::from("localhost"),
+            ];
+            let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+            let expected = Ok(CommandLineArguments {
+                host: "localhost".to_owned(),
+                forge: Some(expected_marker),
+                ..CommandLineArguments::default()
+            });
+            assert_eq!(expected, args, "{marker}");
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid_forge_marker() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--forge"),
+            String::from("fml3"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_quiet() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("-q"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            quiet: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_quiet_with_verbose() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--quiet"),
+            String::from("-v"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(args, Err("-v is incompatible with -q".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_protocol_fallback() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--protocol-fallback"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            protocol_fallback: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_protocol_fallback_with_mc_version() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--protocol-fallback"),
+            String::from("--mc-version"),
+            String::from("1.20.4"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_list_colors() {
+        let cli_args = [String::from("./command"), String::from("--list-colors")];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            list_colors: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_list_colors_with_address() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--list-colors"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(args, Err("Invalid arguments".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_timing() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--timing"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            timing: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_timing_with_compact() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--timing"),
+            String::from("--compact"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(
+            args,
+            Err("--timing is incompatible with --compact".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_clean_motd() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--compact"),
+            String::from("--clean-motd"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            compact: true,
+            clean_motd: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_early_hints() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--early-hints"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            early_hints: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_early_hints_with_wait_online() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--early-hints"),
+            String::from("--wait-online"),
+            String::from("10"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(
+            args,
+            Err("--early-hints is incompatible with --wait-online".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_fail_on_mismatch_only() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--fail-on-mismatch-only"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        let expected = Ok(CommandLineArguments {
+            host: "localhost".to_owned(),
+            fail_on_mismatch_only: true,
+            ..CommandLineArguments::default()
+        });
+        assert_eq!(expected, args);
+    }
+
+    #[test]
+    fn test_parse_fail_on_mismatch_only_with_status_checks() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--fail-on-mismatch-only"),
+            String::from("--expect-version"),
+            String::from("1.20.4"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(
+            args,
+            Err("--fail-on-mismatch-only is incompatible with --expect-version".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_fail_on_mismatch_only_with_no_ping() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--fail-on-mismatch-only"),
+            String::from("--no-ping"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_parse_clean_motd_without_single_line_output() {
+        let cli_args = [
+            String::from("./command"),
+            String::from("--clean-motd"),
+            String::from("localhost"),
+        ];
+        let args = CommandLineArguments::parse_with_env(&mut cli_args.into_iter(), |_| None);
+        assert_eq!(
+            args,
+            Err("--clean-motd can only be used with --compact or --format".to_owned())
+        );
+    }
 }
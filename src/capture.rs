@@ -0,0 +1,52 @@
+use std::io::{self, Read, Write};
+
+// Wraps the reading side of a connection and copies every byte that is read from it to a capture, so real responses
+// can be saved and replayed later. Wrap the buffered reader, not the socket, so only the bytes that were actually
+// consumed end up in the capture.
+pub struct TeeReader<R, W> {
+    inner: R,
+    capture: W,
+}
+
+impl<R, W> TeeReader<R, W> {
+    pub fn new(inner: R, capture: W) -> Self {
+        TeeReader { inner, capture }
+    }
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.inner.read(buf)?;
+        self.capture.write_all(&buf[..length])?;
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tee_reader_tests {
+    use super::*;
+
+    #[test]
+    fn test_captures_bytes_read() {
+        let input: &[u8] = b"Hello, world";
+        let mut capture = vec![];
+        let mut reader = TeeReader::new(input, &mut capture);
+
+        let mut buf = [0; 5];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"Hello");
+        assert_eq!(capture, b"Hello");
+    }
+
+    #[test]
+    fn test_captures_everything_read() {
+        let input: &[u8] = b"Hello, world";
+        let mut capture = vec![];
+        let mut output = vec![];
+        TeeReader::new(input, &mut capture)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, b"Hello, world");
+        assert_eq!(capture, b"Hello, world");
+    }
+}
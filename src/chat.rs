@@ -7,16 +7,124 @@ const UNDERLINE: &str = "\x1B[4m";
 const SLOW_BLINK: &str = "\x1B[5m";
 const STRIKETHROUGH: &str = "\x1B[9m";
 
-pub fn parse_chat_object_json_to_string(text: &Value, apply_styles: bool) -> String {
+const OBFUSCATED_BLOCK: char = '█';
+
+// How to render obfuscated text, which in the game shows random characters that change constantly
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ObfuscateMode {
+    // Make the text blink
+    Blink,
+    // Replace every character with a block
+    Blocks,
+    // Don't show the text at all
+    Hide,
+    // Show the text as if it wasn't obfuscated
+    Plain,
+}
+
+// What to turn the styles of a chat component into
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TextFormat {
+    // ANSI escape sequences for terminals
+    Ansi,
+    // No styles, only the text
+    Plain,
+    // HTML fragment with a <span> for every style. There's no HTML output on the command line yet.
+    #[allow(dead_code)]
+    Html,
+}
+
+// Named colors in the order of their legacy codes, from §0 to §f
+pub const LEGACY_COLOR_NAMES: [&str; 16] = [
+    "black",
+    "dark_blue",
+    "dark_green",
+    "dark_aqua",
+    "dark_red",
+    "dark_purple",
+    "gold",
+    "gray",
+    "dark_gray",
+    "blue",
+    "green",
+    "aqua",
+    "red",
+    "light_purple",
+    "yellow",
+    "white",
+];
+
+const GRADIENT_STEPS: u32 = 64;
+
+// Every legacy color printed in itself, followed by a red to blue gradient. Used to check by eye that the terminal
+// renders the 24-bit colors we output. Without styles only the names are listed.
+pub fn color_chart(apply_styles: bool) -> String {
+    let mut out = String::new();
+    for (code, name) in LEGACY_COLOR_NAMES.iter().enumerate() {
+        let label = format!("§{code:x} {name}");
+        match parse_color(name) {
+            Some(color) if apply_styles => {
+                push_ansi_color_sequence(&mut out, color);
+                out.push_str(&label);
+                out.push_str(RESET_STYLES);
+            }
+            _ => out.push_str(&label),
+        }
+        out.push('\n');
+    }
+
+    if apply_styles {
+        for step in 0..GRADIENT_STEPS {
+            let red = 255 - step * 255 / (GRADIENT_STEPS - 1);
+            let color = Color {
+                red: red as u8,
+                green: 0,
+                blue: (255 - red) as u8,
+            };
+            push_ansi_color_sequence(&mut out, color);
+            out.push(OBFUSCATED_BLOCK);
+        }
+        out.push_str(RESET_STYLES);
+        out.push('\n');
+    }
+
+    out
+}
+
+pub fn parse_chat_object_json_to_string(
+    text: &Value,
+    text_format: TextFormat,
+    obfuscate_mode: ObfuscateMode,
+) -> String {
     // Parse text as a JSON chat object and apply font styles
-    parse_component(text, apply_styles)
+    match text_format {
+        TextFormat::Ansi => render_component(text, AnsiSink::new(obfuscate_mode)),
+        TextFormat::Plain => render_component(text, PlainSink::new(obfuscate_mode)),
+        TextFormat::Html => render_component(text, HtmlSink::new(obfuscate_mode)),
+    }
 }
 
-pub fn parse_styles_to_string(string: &str, actually_apply_styles: bool) -> String {
-    // Parse text and apply styles if requested
-    let mut ret = String::with_capacity(string.len());
-    apply_styles(string, &mut ret, Style::default(), actually_apply_styles);
-    ret
+pub fn parse_styles_to_string(
+    string: &str,
+    text_format: TextFormat,
+    obfuscate_mode: ObfuscateMode,
+) -> String {
+    // Parse text with legacy formatting codes and apply font styles
+    match text_format {
+        TextFormat::Ansi => render_legacy_text(string, AnsiSink::new(obfuscate_mode)),
+        TextFormat::Plain => render_legacy_text(string, PlainSink::new(obfuscate_mode)),
+        TextFormat::Html => render_legacy_text(string, HtmlSink::new(obfuscate_mode)),
+    }
+}
+
+fn render_component<S: StyleSink>(text: &Value, mut sink: S) -> String {
+    parse_component(text, &mut sink);
+    sink.finish()
+}
+
+fn render_legacy_text<S: StyleSink>(string: &str, mut sink: S) -> String {
+    apply_styles(string, &mut sink, Style::default());
+    sink.finish()
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -36,15 +144,227 @@ struct Style {
     color: Option<Color>,
 }
 
-fn parse_component(text: &Value, actually_apply_styles: bool) -> String {
-    let mut str = String::new();
+// Receives the styled text of a chat component. Each output format has its own sink, so walking the components
+// doesn't need to know anything about the format.
+trait StyleSink {
+    // Applies a style on top of the ones that are already active
+    fn begin_style(&mut self, style: &Style);
+
+    fn text(&mut self, text: &str);
+
+    // Removes every style applied since the last call
+    fn end_style(&mut self);
+
+    // Returns everything written so far
+    fn finish(self) -> String;
+}
+
+// Text with ANSI escape sequences for terminals
+struct AnsiSink {
+    out: String,
+    obfuscate_mode: ObfuscateMode,
+    obfuscated: bool,
+}
+
+impl AnsiSink {
+    fn new(obfuscate_mode: ObfuscateMode) -> Self {
+        AnsiSink {
+            out: String::new(),
+            obfuscate_mode,
+            obfuscated: false,
+        }
+    }
+}
+
+impl StyleSink for AnsiSink {
+    fn begin_style(&mut self, style: &Style) {
+        self.obfuscated |= style.obfuscated;
+
+        if let Some(color) = style.color {
+            push_ansi_color_sequence(&mut self.out, color);
+        }
+
+        if style.bold {
+            self.out.push_str(BOLD);
+        }
+
+        if style.italic {
+            self.out.push_str(ITALIC);
+        }
+
+        if style.underline {
+            self.out.push_str(UNDERLINE);
+        }
+
+        if style.strikethrough {
+            self.out.push_str(STRIKETHROUGH);
+        }
+
+        if style.obfuscated && self.obfuscate_mode == ObfuscateMode::Blink {
+            // ANSI colors doesn't support showing random text, so we blink it instead. Better than nothing, I guess...
+            self.out.push_str(SLOW_BLINK);
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.obfuscated {
+            self.out.push_str(&obfuscate(text, self.obfuscate_mode));
+        } else {
+            self.out.push_str(text);
+        }
+    }
+
+    fn end_style(&mut self) {
+        self.obfuscated = false;
+        self.out.push_str(RESET_STYLES);
+    }
+
+    fn finish(self) -> String {
+        self.out
+    }
+}
+
+// Text without any styles
+struct PlainSink {
+    out: String,
+    obfuscate_mode: ObfuscateMode,
+    obfuscated: bool,
+}
+
+impl PlainSink {
+    fn new(obfuscate_mode: ObfuscateMode) -> Self {
+        PlainSink {
+            out: String::new(),
+            obfuscate_mode,
+            obfuscated: false,
+        }
+    }
+}
+
+impl StyleSink for PlainSink {
+    fn begin_style(&mut self, style: &Style) {
+        self.obfuscated |= style.obfuscated;
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.obfuscated {
+            self.out.push_str(&obfuscate(text, self.obfuscate_mode));
+        } else {
+            self.out.push_str(text);
+        }
+    }
+
+    fn end_style(&mut self) {
+        self.obfuscated = false;
+    }
+
+    fn finish(self) -> String {
+        self.out
+    }
+}
+
+// HTML fragment with a <span> for every style
+struct HtmlSink {
+    out: String,
+    obfuscate_mode: ObfuscateMode,
+    obfuscated: bool,
+    open_spans: usize,
+}
+
+impl HtmlSink {
+    fn new(obfuscate_mode: ObfuscateMode) -> Self {
+        HtmlSink {
+            out: String::new(),
+            obfuscate_mode,
+            obfuscated: false,
+            open_spans: 0,
+        }
+    }
+}
+
+impl StyleSink for HtmlSink {
+    fn begin_style(&mut self, style: &Style) {
+        self.obfuscated |= style.obfuscated;
+
+        let mut css = vec![];
+        if let Some(color) = style.color {
+            css.push(format!(
+                "color: #{:02x}{:02x}{:02x}",
+                color.red, color.green, color.blue
+            ));
+        }
+
+        if style.bold {
+            css.push("font-weight: bold".to_owned());
+        }
+
+        if style.italic {
+            css.push("font-style: italic".to_owned());
+        }
+
+        let mut decorations = vec![];
+        if style.underline {
+            decorations.push("underline");
+        }
+
+        if style.strikethrough {
+            decorations.push("line-through");
+        }
+
+        if style.obfuscated && self.obfuscate_mode == ObfuscateMode::Blink {
+            decorations.push("blink");
+        }
+
+        if !decorations.is_empty() {
+            css.push(format!("text-decoration: {}", decorations.join(" ")));
+        }
+
+        if !css.is_empty() {
+            self.out.push_str("<span style=\"");
+            self.out.push_str(&css.join("; "));
+            self.out.push_str("\">");
+            self.open_spans += 1;
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let text = if self.obfuscated {
+            obfuscate(text, self.obfuscate_mode)
+        } else {
+            text.to_owned()
+        };
+        for c in text.chars() {
+            match c {
+                '&' => self.out.push_str("&amp;"),
+                '<' => self.out.push_str("&lt;"),
+                '>' => self.out.push_str("&gt;"),
+                '"' => self.out.push_str("&quot;"),
+                '\'' => self.out.push_str("&#39;"),
+                c => self.out.push(c),
+            }
+        }
+    }
+
+    fn end_style(&mut self) {
+        self.obfuscated = false;
+        for _ in 0..self.open_spans {
+            self.out.push_str("</span>");
+        }
+        self.open_spans = 0;
+    }
+
+    fn finish(self) -> String {
+        self.out
+    }
+}
 
+fn parse_component<S: StyleSink>(text: &Value, sink: &mut S) {
     // Parse all components recursively and implement style inheritance for the current system (doesn't apply for the old system)
     let mut components = vec![(text, Style::default())];
     while let Some((comp, style)) = components.pop() {
         match comp {
             Value::Null => {} // Null is ignored
-            Value::String(t) => apply_styles(t, &mut str, style, actually_apply_styles),
+            Value::String(t) => apply_styles(t, sink, style),
             Value::Object(chat_object) => {
                 // Set styles for this component
                 let mut style = style;
@@ -68,13 +388,18 @@ fn parse_component(text: &Value, actually_apply_styles: bool) -> String {
                     style.obfuscated = *obfuscated;
                 }
 
-                if let Some(Value::String(color)) = chat_object.get("color") {
-                    style.color = parse_color(color);
+                match chat_object.get("color") {
+                    Some(Value::String(color)) => style.color = parse_color(color),
+                    // Newer releases may encode the color as a packed 0xRRGGBB number
+                    Some(Value::Number(color)) => {
+                        style.color = color.as_u64().and_then(parse_packed_color)
+                    }
+                    _ => {}
                 }
 
                 // Parse string
                 if let Some(Value::String(s)) = &chat_object.get("text") {
-                    apply_styles(s, &mut str, style, actually_apply_styles);
+                    apply_styles(s, sink, style);
                 }
 
                 // Parse sibling components. If the "extra" property is not an array we ignore it.
@@ -89,111 +414,142 @@ fn parse_component(text: &Value, actually_apply_styles: bool) -> String {
                     components.push((sibling, style));
                 }
             }
-            t => apply_styles(&t.to_string(), &mut str, style, actually_apply_styles), // Convert booleans and numbers into a string
+            t => apply_styles(&t.to_string(), sink, style), // Convert booleans and numbers into a string
         }
     }
-    str
 }
 
-fn apply_styles(str: &str, out: &mut String, style: Style, actually_apply_styles: bool) {
+fn apply_styles<S: StyleSink>(str: &str, sink: &mut S, style: Style) {
     // Apply formatting using the current style inheritance system. Override styles from the parent style if needed.
     let mut str_iter = str.chars();
     let string_to_style: String = str_iter.by_ref().take_while(|c| *c != '§').collect();
-
-    if actually_apply_styles {
-        if let Some(color) = style.color {
-            let red = color.red.to_string();
-            let green = color.green.to_string();
-            let blue = color.blue.to_string();
-            push_ansi_color_sequence(out, &red, &green, &blue);
-        }
-
-        if style.bold {
-            out.push_str(BOLD);
-        }
-
-        if style.italic {
-            out.push_str(ITALIC);
-        }
-
-        if style.underline {
-            out.push_str(UNDERLINE);
-        }
-
-        if style.strikethrough {
-            out.push_str(STRIKETHROUGH);
-        }
-
-        if style.obfuscated {
-            // ANSI colors doesn't support showing random text, so we blink it instead. Better than nothing, I guess...
-            out.push_str(SLOW_BLINK);
-        }
-    }
-
-    out.push_str(&string_to_style);
-    if actually_apply_styles {
-        out.push_str(RESET_STYLES);
-    }
+    sink.begin_style(&style);
+    sink.text(&string_to_style);
+    sink.end_style();
 
     // Apply formatting using the old system. This system takes precedence over the current system and doesn't participate
     // in the style inheritance system, so any styles applied here don't propagate to child components.
     // The way this old system work is very similar to ANSI colors in terminals. It will apply a style based on a control
     // sequence until it finds a reset sequence. It is possible to apply multiple styles at once.
     while let Some(control_sequence) = str_iter.next() {
+        if control_sequence == 'x' {
+            if let Some((color, rest)) = parse_legacy_hex_color(str_iter.as_str()) {
+                sink.begin_style(&Style {
+                    color: Some(color),
+                    ..Style::default()
+                });
+                str_iter = rest.chars();
+            }
+        }
         let string_to_style: String = str_iter.by_ref().take_while(|c| *c != '§').collect();
-        if actually_apply_styles {
-            match control_sequence {
-                // Colors
-                '0' => push_ansi_color_sequence(out, "0", "0", "0"),
-                '1' => push_ansi_color_sequence(out, "0", "0", "170"),
-                '2' => push_ansi_color_sequence(out, "0", "170", "0"),
-                '3' => push_ansi_color_sequence(out, "0", "170", "170"),
-                '4' => push_ansi_color_sequence(out, "170", "0", "0"),
-                '5' => push_ansi_color_sequence(out, "170", "0", "170"),
-                '6' => push_ansi_color_sequence(out, "255", "170", "0"),
-                '7' => push_ansi_color_sequence(out, "170", "170", "170"),
-                '8' => push_ansi_color_sequence(out, "85", "85", "85"),
-                '9' => push_ansi_color_sequence(out, "85", "85", "255"),
-                'a' => push_ansi_color_sequence(out, "85", "255", "85"),
-                'b' => push_ansi_color_sequence(out, "85", "255", "255"),
-                'c' => push_ansi_color_sequence(out, "255", "85", "85"),
-                'd' => push_ansi_color_sequence(out, "255", "85", "255"),
-                'e' => push_ansi_color_sequence(out, "255", "255", "85"),
-                'f' => push_ansi_color_sequence(out, "255", "255", "255"),
-
-                // Styles
-                'k' => out.push_str(SLOW_BLINK), // Obfuscated
-                'l' => out.push_str(BOLD),
-                'm' => out.push_str(STRIKETHROUGH),
-                'n' => out.push_str(UNDERLINE),
-                'o' => out.push_str(ITALIC),
-                'r' => out.push_str(RESET_STYLES),
-
-                _ => {}
-            };
+        if control_sequence == 'r' {
+            sink.end_style();
+        } else if let Some(style) = parse_legacy_code(control_sequence) {
+            sink.begin_style(&style);
         }
 
-        out.push_str(string_to_style.as_ref());
+        sink.text(&string_to_style);
         // NOTE: We should only reset styles if we encounter the 'r' character or we stop using the old style system
     }
 
-    if actually_apply_styles {
-        out.push_str(RESET_STYLES);
+    sink.end_style();
+}
+
+// Newer servers send 24-bit colors in the old system as §x followed by one code for every hex digit, like §x§f§f§0§0§0§0
+// for red. Takes the text right after §x and returns the color and the text that follows the last digit.
+fn parse_legacy_hex_color(str: &str) -> Option<(Color, &str)> {
+    let mut rest = str;
+    let mut rgb = 0;
+    for _ in 0..6 {
+        let mut chars = rest.strip_prefix('§')?.chars();
+        rgb = rgb << 4 | chars.next()?.to_digit(16)?;
+        rest = chars.as_str();
     }
+    Some((
+        Color {
+            red: (rgb >> 16) as u8,
+            green: (rgb >> 8) as u8,
+            blue: rgb as u8,
+        },
+        rest,
+    ))
 }
 
-fn push_ansi_color_sequence(out: &mut String, red: &str, green: &str, blue: &str) {
-    // Using 24-bit colors in the format of "38;2;R;G;B", where R, G and B are decimal values in the range of [0-255]
-    out.push_str("\x1B[38;2;");
+fn parse_legacy_code(control_sequence: char) -> Option<Style> {
+    // The style that a single control sequence of the old system adds on top of the current one
+    let color = |red, green, blue| Style {
+        color: Some(Color { red, green, blue }),
+        ..Style::default()
+    };
+    let style = match control_sequence {
+        // Colors
+        '0' => color(0, 0, 0),
+        '1' => color(0, 0, 170),
+        '2' => color(0, 170, 0),
+        '3' => color(0, 170, 170),
+        '4' => color(170, 0, 0),
+        '5' => color(170, 0, 170),
+        '6' => color(255, 170, 0),
+        '7' => color(170, 170, 170),
+        '8' => color(85, 85, 85),
+        '9' => color(85, 85, 255),
+        'a' => color(85, 255, 85),
+        'b' => color(85, 255, 255),
+        'c' => color(255, 85, 85),
+        'd' => color(255, 85, 255),
+        'e' => color(255, 255, 85),
+        'f' => color(255, 255, 255),
 
-    out.push_str(red);
-    out.push(';');
+        // Styles
+        'k' => Style {
+            obfuscated: true,
+            ..Style::default()
+        },
+        'l' => Style {
+            bold: true,
+            ..Style::default()
+        },
+        'm' => Style {
+            strikethrough: true,
+            ..Style::default()
+        },
+        'n' => Style {
+            underline: true,
+            ..Style::default()
+        },
+        'o' => Style {
+            italic: true,
+            ..Style::default()
+        },
+
+        _ => return None,
+    };
+    Some(style)
+}
 
-    out.push_str(green);
-    out.push(';');
+fn obfuscate(str: &str, obfuscate_mode: ObfuscateMode) -> String {
+    match obfuscate_mode {
+        ObfuscateMode::Blink | ObfuscateMode::Plain => str.to_owned(),
+        ObfuscateMode::Blocks => str
+            .chars()
+            .map(|c| {
+                if c.is_whitespace() {
+                    c
+                } else {
+                    OBFUSCATED_BLOCK
+                }
+            })
+            .collect(),
+        ObfuscateMode::Hide => String::new(),
+    }
+}
 
-    out.push_str(blue);
-    out.push('m');
+fn push_ansi_color_sequence(out: &mut String, color: Color) {
+    // Using 24-bit colors in the format of "38;2;R;G;B", where R, G and B are decimal values in the range of [0-255]
+    out.push_str(&format!(
+        "\x1B[38;2;{};{};{}m",
+        color.red, color.green, color.blue
+    ));
 }
 
 fn parse_color(color: &str) -> Option<Color> {
@@ -282,6 +638,18 @@ fn parse_color(color: &str) -> Option<Color> {
     }
 }
 
+fn parse_packed_color(color: u64) -> Option<Color> {
+    if color > 0xFFFFFF {
+        return None;
+    }
+
+    Some(Color {
+        red: (color >> 16) as u8,
+        green: (color >> 8) as u8,
+        blue: color as u8,
+    })
+}
+
 fn parse_web_color(color: &str) -> Option<Color> {
     // TODO: Support more formats
     if color.starts_with('#') && color.len() == 7 {
@@ -304,13 +672,14 @@ mod chat_component_tests {
     use super::*;
     use serde_json::json;
 
-    const APPLY_FONT_STYLES: bool = false;
+    const TEXT_FORMAT: TextFormat = TextFormat::Plain;
+    const OBFUSCATE_MODE: ObfuscateMode = ObfuscateMode::Blink;
 
     #[test]
     fn test_parse_null() {
         let text = json!(null);
         let expected = "";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -318,7 +687,7 @@ mod chat_component_tests {
     fn test_parse_boolean() {
         let text = json!(true);
         let expected = "true";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -326,7 +695,7 @@ mod chat_component_tests {
     fn test_parse_number() {
         let text = json!(23.4);
         let expected = "23.4";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -334,7 +703,7 @@ mod chat_component_tests {
     fn test_parse_string() {
         let text = json!("THIS IS TEXT");
         let expected = "THIS IS TEXT";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -342,7 +711,7 @@ mod chat_component_tests {
     fn test_parse_empty_object_component() {
         let text = json!({});
         let expected = "";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -354,7 +723,7 @@ mod chat_component_tests {
             }
         );
         let expected = "THIS IS TEXT";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -374,7 +743,7 @@ mod chat_component_tests {
             }
         );
         let expected = "THIS IS TEXT";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -396,7 +765,7 @@ mod chat_component_tests {
             }
         );
         let expected = "THIS IS TEXT";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -421,7 +790,7 @@ mod chat_component_tests {
             }
         );
         let expected = "THIS IS SOME TEXT";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -435,7 +804,7 @@ mod chat_component_tests {
             }
         );
         let expected = "";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -450,7 +819,7 @@ mod chat_component_tests {
             }
         );
         let expected = "THIS IS A";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -458,7 +827,7 @@ mod chat_component_tests {
     fn test_parse_empty_array() {
         let text = json!([]);
         let expected = "";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -466,7 +835,7 @@ mod chat_component_tests {
     fn test_parse_array_of_primitive_types() {
         let text = json!([true, false, 45.6]);
         let expected = "truefalse45.6";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -474,7 +843,7 @@ mod chat_component_tests {
     fn test_parse_array_of_strings() {
         let text = json!(["Hello, ", "world!"]);
         let expected = "Hello, world!";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -482,7 +851,7 @@ mod chat_component_tests {
     fn test_parse_nested_arrays_of_strings() {
         let text = json!([[["Hello, ", "world!"]]]);
         let expected = "Hello, world!";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
 
@@ -496,7 +865,320 @@ mod chat_component_tests {
             ]
         );
         let expected = "Hello, world!";
-        let result = parse_chat_object_json_to_string(&text, APPLY_FONT_STYLES);
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+}
+
+#[cfg(test)]
+mod legacy_style_tests {
+    use super::*;
+    use serde_json::json;
+
+    const TEXT_FORMAT: TextFormat = TextFormat::Ansi;
+    const OBFUSCATE_MODE: ObfuscateMode = ObfuscateMode::Blink;
+
+    fn expected_output(style_sequence: &str) -> String {
+        expected_output_for(style_sequence, "X")
+    }
+
+    fn expected_output_for(style_sequence: &str, text: &str) -> String {
+        // The text before the first control sequence is empty, but it's still reset before applying the legacy styles
+        format!("{RESET_STYLES}{style_sequence}{text}{RESET_STYLES}")
+    }
+
+    #[test]
+    fn test_legacy_color_codes() {
+        let colors = [
+            ('0', "0;0;0"),
+            ('1', "0;0;170"),
+            ('2', "0;170;0"),
+            ('3', "0;170;170"),
+            ('4', "170;0;0"),
+            ('5', "170;0;170"),
+            ('6', "255;170;0"),
+            ('7', "170;170;170"),
+            ('8', "85;85;85"),
+            ('9', "85;85;255"),
+            ('a', "85;255;85"),
+            ('b', "85;255;255"),
+            ('c', "255;85;85"),
+            ('d', "255;85;255"),
+            ('e', "255;255;85"),
+            ('f', "255;255;255"),
+        ];
+        for (code, rgb) in colors {
+            let text = format!("§{code}X");
+            let expected = expected_output(&format!("\x1B[38;2;{rgb}m"));
+            let result = parse_styles_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
+            assert_eq!(expected, result, "color code §{code}");
+        }
+    }
+
+    #[test]
+    fn test_legacy_style_codes() {
+        let styles = [
+            ('k', SLOW_BLINK),
+            ('l', BOLD),
+            ('m', STRIKETHROUGH),
+            ('n', UNDERLINE),
+            ('o', ITALIC),
+            ('r', RESET_STYLES),
+        ];
+        for (code, sequence) in styles {
+            let text = format!("§{code}X");
+            let expected = expected_output(sequence);
+            let result = parse_styles_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
+            assert_eq!(expected, result, "style code §{code}");
+        }
+    }
+
+    #[test]
+    fn test_legacy_hex_color() {
+        let expected = expected_output("\x1B[38;2;255;0;0m");
+        let result = parse_styles_to_string("§x§f§f§0§0§0§0X", TEXT_FORMAT, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_legacy_hex_color_with_mixed_case_digits() {
+        let expected = expected_output("\x1B[38;2;18;171;205m");
+        let result = parse_styles_to_string("§x§1§2§A§b§C§dX", TEXT_FORMAT, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_incomplete_legacy_hex_color_is_ignored() {
+        // Without all six digits the §x is dropped and the digit is read as a regular color code
+        let expected = expected_output("\x1B[38;2;255;85;85m");
+        let result = parse_styles_to_string("§x§cX", TEXT_FORMAT, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_unknown_legacy_code_is_ignored() {
+        let expected = expected_output("");
+        let result = parse_styles_to_string("§zX", TEXT_FORMAT, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_stacked_legacy_codes() {
+        let expected = expected_output(&format!("\x1B[38;2;255;85;85m{BOLD}"));
+        let result = parse_styles_to_string("§c§lX", TEXT_FORMAT, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_legacy_color_code_in_chat_component() {
+        let text = json!("§cX");
+        let expected = expected_output("\x1B[38;2;255;85;85m");
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_packed_integer_color() {
+        let text = json!({"text": "hi", "color": 16711680});
+        let expected = format!("\x1B[38;2;255;0;0mhi{RESET_STYLES}{RESET_STYLES}");
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_named_color_next_to_packed_color() {
+        let text = json!([{"text": "a", "color": "red"}, {"text": "b", "color": 0x55FF55}]);
+        let expected = format!(
+            "\x1B[38;2;255;85;85ma{RESET_STYLES}{RESET_STYLES}\x1B[38;2;85;255;85mb{RESET_STYLES}{RESET_STYLES}"
+        );
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_out_of_range_packed_color_is_ignored() {
+        let text = json!({"text": "hi", "color": 0x1000000});
+        let expected = format!("hi{RESET_STYLES}{RESET_STYLES}");
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_obfuscated_legacy_text_as_blocks() {
+        let expected = format!("{RESET_STYLES}{BOLD}ab███ █{RESET_STYLES}cd{RESET_STYLES}");
+        let result = parse_styles_to_string("§lab§kxyz w§rcd", TEXT_FORMAT, ObfuscateMode::Blocks);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_obfuscated_component_as_blocks() {
+        let text = json!({"text": "ab", "obfuscated": true});
+        let expected = "██";
+        let result =
+            parse_chat_object_json_to_string(&text, TextFormat::Plain, ObfuscateMode::Blocks);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_obfuscated_component_hidden() {
+        let text = json!(["a", {"text": "b", "obfuscated": true}, "c"]);
+        let expected = "ac";
+        let result =
+            parse_chat_object_json_to_string(&text, TextFormat::Plain, ObfuscateMode::Hide);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_plain_string_description_with_legacy_codes() {
+        // Some servers send the description as a bare string with legacy codes instead of a chat object
+        let text = json!("§aHello");
+        let expected = expected_output_for("\x1B[38;2;85;255;85m", "Hello");
+        let result = parse_chat_object_json_to_string(&text, TEXT_FORMAT, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+}
+
+#[cfg(test)]
+mod parse_styles_tests {
+    use super::*;
+
+    const OBFUSCATE_MODE: ObfuscateMode = ObfuscateMode::Blink;
+
+    #[test]
+    fn test_plain_text_passthrough() {
+        let expected = "A Minecraft Server";
+        let result =
+            parse_styles_to_string("A Minecraft Server", TextFormat::Plain, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_plain_text_with_styles() {
+        let expected = format!("A Minecraft Server{RESET_STYLES}{RESET_STYLES}");
+        let result = parse_styles_to_string("A Minecraft Server", TextFormat::Ansi, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_single_color_code() {
+        let expected =
+            format!("A {RESET_STYLES}\x1B[38;2;255;85;85mMinecraft Server{RESET_STYLES}");
+        let result =
+            parse_styles_to_string("A §cMinecraft Server", TextFormat::Ansi, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_multiple_stacked_codes() {
+        let expected =
+            format!("{RESET_STYLES}\x1B[38;2;85;255;85m{BOLD}{UNDERLINE}Server{RESET_STYLES}");
+        let result = parse_styles_to_string("§a§l§nServer", TextFormat::Ansi, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_trailing_section_sign_without_code() {
+        let expected = "Server";
+        let result = parse_styles_to_string("Server§", TextFormat::Plain, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_codes_are_stripped_without_styles() {
+        let expected = "A Minecraft Server";
+        let result = parse_styles_to_string(
+            "§aA §lMinecraft §rServer",
+            TextFormat::Plain,
+            OBFUSCATE_MODE,
+        );
+        assert_eq!(expected, result);
+    }
+}
+
+#[cfg(test)]
+mod html_sink_tests {
+    use super::*;
+    use serde_json::json;
+
+    const OBFUSCATE_MODE: ObfuscateMode = ObfuscateMode::Blink;
+
+    #[test]
+    fn test_plain_text_is_escaped() {
+        let expected = "&lt;b&gt;Tom &amp; Jerry&lt;/b&gt;";
+        let result = parse_chat_object_json_to_string(
+            &json!("<b>Tom & Jerry</b>"),
+            TextFormat::Html,
+            OBFUSCATE_MODE,
+        );
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_styled_component() {
+        let expected = "<span style=\"color: #ff5555; font-weight: bold; text-decoration: underline\">Server</span>";
+        let result = parse_chat_object_json_to_string(
+            &json!({"text": "Server", "color": "red", "bold": true, "underlined": true}),
+            TextFormat::Html,
+            OBFUSCATE_MODE,
+        );
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_stacked_legacy_codes_are_closed_on_reset() {
+        let expected =
+            "<span style=\"color: #55ff55\"><span style=\"font-weight: bold\">A</span></span>B";
+        let result =
+            parse_chat_object_json_to_string(&json!("§a§lA§rB"), TextFormat::Html, OBFUSCATE_MODE);
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_legacy_codes_as_html() {
+        let expected = "<span style=\"color: #ff5555\">A &amp; B</span>";
+        let result = parse_styles_to_string("§cA & B", TextFormat::Html, OBFUSCATE_MODE);
         assert_eq!(expected, result);
     }
+
+    #[test]
+    fn test_obfuscated_text_as_blocks() {
+        let expected = "██ ██";
+        let result = parse_chat_object_json_to_string(
+            &json!({"text": "ab cd", "obfuscated": true}),
+            TextFormat::Html,
+            ObfuscateMode::Blocks,
+        );
+        assert_eq!(expected, result);
+    }
+}
+
+#[cfg(test)]
+mod color_chart_tests {
+    use super::*;
+
+    #[test]
+    fn test_color_chart_without_styles() {
+        let chart = color_chart(false);
+        assert_eq!(chart.lines().count(), 16);
+        assert_eq!(chart.lines().next(), Some("§0 black"));
+        assert_eq!(chart.lines().last(), Some("§f white"));
+        assert!(!chart.contains('\x1B'));
+    }
+
+    #[test]
+    fn test_color_chart_with_styles() {
+        let chart = color_chart(true);
+        // 16 colors plus the gradient
+        assert_eq!(chart.lines().count(), 17);
+        assert!(chart.starts_with("\x1B[38;2;0;0;0m§0 black\x1B[0m\n"));
+        assert!(chart.contains("\x1B[38;2;255;0;0m█"));
+        assert!(chart.contains("\x1B[38;2;0;0;255m█"));
+    }
+
+    #[test]
+    fn test_legacy_color_names_are_known() {
+        for name in LEGACY_COLOR_NAMES {
+            assert!(parse_color(name).is_some(), "{name}");
+        }
+    }
 }
@@ -1,10 +1,15 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::io::{Read, Write};
 
-#[derive(Deserialize)]
+// Big enough for any status response with a favicon. The vanilla client doesn't accept anything bigger than 2 MiB.
+pub const DEFAULT_MAX_PACKET_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Deserialize, Default)]
 pub struct Response {
-    pub version: ResponseVersion,
-    pub players: ResponsePlayers,
+    // Some modded servers and proxies omit these fields entirely, so they are optional to let us still print the MOTD
+    pub version: Option<ResponseVersion>,
+    pub players: Option<ResponsePlayers>,
     pub description: serde_json::Value,
     pub favicon: Option<String>,
 
@@ -13,15 +18,65 @@ pub struct Response {
 
     #[serde(rename = "previewsChat")]
     pub previews_chat: Option<bool>,
+
+    // Any other top-level field, so new ones can be shown before we know what they mean
+    #[serde(flatten)]
+    pub extra_fields: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Deserialize)]
+#[serde(from = "LenientResponseVersion")]
 pub struct ResponseVersion {
     pub name: String,
     pub protocol: i32,
+    // Set when the server sent fields we had to fill in or convert
+    pub coerced: bool,
+}
+
+impl Default for ResponseVersion {
+    fn default() -> Self {
+        ResponseVersion {
+            name: "unknown".to_owned(),
+            protocol: -1,
+            coerced: false,
+        }
+    }
 }
 
+// Some proxies omit the name or send the protocol number as a string. Accept anything and sort it out afterwards.
 #[derive(Deserialize)]
+struct LenientResponseVersion {
+    name: Option<String>,
+    protocol: Option<serde_json::Value>,
+}
+
+impl From<LenientResponseVersion> for ResponseVersion {
+    fn from(version: LenientResponseVersion) -> Self {
+        let mut result = ResponseVersion::default();
+        match version.name {
+            Some(name) => result.name = name,
+            None => result.coerced = true,
+        }
+        match version.protocol {
+            Some(serde_json::Value::Number(protocol)) => {
+                match protocol.as_i64().and_then(|p| i32::try_from(p).ok()) {
+                    Some(protocol) => result.protocol = protocol,
+                    None => result.coerced = true,
+                }
+            }
+            Some(serde_json::Value::String(protocol)) => {
+                result.coerced = true;
+                if let Ok(protocol) = protocol.trim().parse() {
+                    result.protocol = protocol;
+                }
+            }
+            _ => result.coerced = true,
+        }
+        result
+    }
+}
+
+#[derive(Deserialize, Default)]
 pub struct ResponsePlayers {
     pub max: i32,
     pub online: i32,
@@ -51,6 +106,50 @@ pub fn write_var_int<T: Write>(output: &mut T, value: i32) -> Result<(), String>
     Err("Attempting to write more than 5 bytes of data for VarInt {value:#x} ({value})".to_owned())
 }
 
+// Not used by any packet we handle yet
+#[allow(dead_code)]
+pub fn write_var_long<T: Write>(output: &mut T, value: i64) -> Result<(), String> {
+    // Same encoding as VarInt, but for 64-bit numbers. The length varies from 1 to 10 bytes as maximum.
+    const CONTINUE_BIT: u8 = 0b10000000;
+    const SEGMENT_BITS: u64 = 0b01111111;
+    let value: u64 = value as u64;
+    for i in 0..10 {
+        let next_value = value >> (i * 7);
+        let segment_data = next_value & SEGMENT_BITS;
+        if (next_value & !SEGMENT_BITS) == 0 {
+            output
+                .write_all(&[segment_data as u8])
+                .map_err(|e| e.to_string())?;
+            return Ok(());
+        } else {
+            output
+                .write_all(&[segment_data as u8 | CONTINUE_BIT])
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    // We should not reach this point!
+    Err(format!(
+        "Attempting to write more than 10 bytes of data for VarLong {value:#x} ({value})"
+    ))
+}
+
+// Handy to build packets by hand. Not used by any packet we handle yet.
+#[allow(dead_code)]
+pub fn var_int_bytes(value: i32) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(5);
+    write_var_int(&mut bytes, value).expect("writing to a Vec never fails");
+    bytes
+}
+
+// Handy to build packets by hand. Not used by any packet we handle yet.
+#[allow(dead_code)]
+pub fn var_long_bytes(value: i64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(10);
+    write_var_long(&mut bytes, value).expect("writing to a Vec never fails");
+    bytes
+}
+
 pub fn read_var_int<T: Read>(input: &mut T) -> Result<i32, String> {
     // Signed, little-endian, variable-length number. The length varies from 1 to 5 bytes as maximum.
     const CONTINUE_BIT: u8 = 0b10000000;
@@ -58,10 +157,11 @@ pub fn read_var_int<T: Read>(input: &mut T) -> Result<i32, String> {
     let mut num: u32 = 0;
 
     // Read at most five bytes
-    for (i, next) in input.take(5).bytes().enumerate() {
-        if let Ok(byte) = next {
-            num |= ((byte & SEGMENT_BITS) as u32) << (i * 7);
-            if byte & CONTINUE_BIT == 0 {
+    let mut byte = [0; 1];
+    for i in 0..5 {
+        if input.read_exact(&mut byte).is_ok() {
+            num |= ((byte[0] & SEGMENT_BITS) as u32) << (i * 7);
+            if byte[0] & CONTINUE_BIT == 0 {
                 return Ok(num as i32);
             }
         } else {
@@ -87,6 +187,19 @@ pub fn write_string<T: Write>(output: &mut T, value: &str) -> Result<(), String>
 pub fn read_string<T: Read>(input: &mut T) -> Result<String, String> {
     // UTF-8 string prefixed with a size as a VarInt. We will use the built-in String data type as it already supports
     // UTF-8 out of the box.
+    let utf8_data = read_string_bytes(input)?;
+    let string = String::from_utf8(utf8_data).map_err(|e| e.to_string())?;
+    Ok(string)
+}
+
+pub fn read_string_lossy<T: Read>(input: &mut T) -> Result<String, String> {
+    // Same as read_string, but invalid UTF-8 sequences are replaced with U+FFFD instead of failing. Some buggy servers
+    // send Latin-1 or truncated UTF-8.
+    let utf8_data = read_string_bytes(input)?;
+    Ok(String::from_utf8_lossy(&utf8_data).into_owned())
+}
+
+fn read_string_bytes<T: Read>(input: &mut T) -> Result<Vec<u8>, String> {
     let size = read_var_int(input)?;
     let size: usize = size
         .try_into()
@@ -97,8 +210,7 @@ pub fn read_string<T: Read>(input: &mut T) -> Result<String, String> {
     input
         .read_exact(&mut utf8_data)
         .map_err(|e| e.to_string())?;
-    let string = String::from_utf8(utf8_data).map_err(|e| e.to_string())?;
-    Ok(string)
+    Ok(utf8_data)
 }
 
 pub fn read_long<T: Read>(input: &mut T) -> Result<i64, String> {
@@ -124,10 +236,112 @@ pub fn write_long<T: Write>(output: &mut T, value: i64) -> Result<(), String> {
     Ok(())
 }
 
+// Not used by any packet we handle yet
+#[allow(dead_code)]
+pub fn read_position<T: Read>(input: &mut T) -> Result<(i32, i32, i32), String> {
+    // x (26 bits), z (26 bits) and y (12 bits) packed in a big-endian 64-bit integer, each one in two's complement.
+    // Shifting left and then arithmetically right sign-extends every field.
+    let value = read_long(input)?;
+    let x = value >> 38;
+    let y = (value << 52) >> 52;
+    let z = (value << 26) >> 38;
+    Ok((x as i32, y as i32, z as i32))
+}
+
+// Not used by any packet we handle yet
+#[allow(dead_code)]
+pub fn write_position<T: Write>(output: &mut T, x: i32, y: i32, z: i32) -> Result<(), String> {
+    let horizontal_range = -(1 << 25)..(1 << 25);
+    let vertical_range = -(1 << 11)..(1 << 11);
+    if !horizontal_range.contains(&x)
+        || !vertical_range.contains(&y)
+        || !horizontal_range.contains(&z)
+    {
+        return Err(format!("Position ({x}, {y}, {z}) is out of range"));
+    }
+
+    let value =
+        ((x as i64 & 0x3FF_FFFF) << 38) | ((z as i64 & 0x3FF_FFFF) << 12) | (y as i64 & 0xFFF);
+    write_long(output, value)
+}
+
+pub fn write_packet<T: Write>(output: &mut T, packet_id: i32, body: &[u8]) -> Result<(), String> {
+    // Packet ID and body prefixed with the size of both as a VarInt
+    let mut packet: Vec<u8> = Vec::with_capacity(body.len() + 5);
+    write_var_int(&mut packet, packet_id)?;
+    packet.extend_from_slice(body);
+
+    let packet_length = i32::try_from(packet.len()).map_err(|_| "could not write packet because its length exceeds the maximum size that can be represented as a VarInt".to_owned())?;
+    write_var_int(output, packet_length)?;
+    output.write_all(&packet).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub fn read_packet<T: Read>(
+    input: &mut T,
+    max_packet_size: usize,
+) -> Result<(i32, Vec<u8>), String> {
+    // Packet length
+    let packet_length = read_var_int(input)?;
+    if packet_length < 0 {
+        return Err(format!("Invalid packet length: {packet_length}"));
+    }
+    // Every packet has at least an ID. Some broken servers send nothing at all.
+    if packet_length == 0 {
+        return Err("The server sent an empty packet".to_owned());
+    }
+    // Don't trust the server with the amount of data we are willing to read
+    if packet_length as usize > max_packet_size {
+        return Err(format!(
+            "Packet length {packet_length} exceeds the maximum packet size of {max_packet_size} bytes"
+        ));
+    }
+
+    // Here we will ensure that we don't read more than **packet_length** bytes for this packet
+    let mut input = input.take(packet_length as u64);
+
+    // Packet ID
+    let packet_id = read_var_int(&mut input)?;
+
+    // The rest of the packet is the body. It must be exactly as long as the packet length says.
+    let mut body = vec![];
+    input.read_to_end(&mut body).map_err(|e| e.to_string())?;
+    if input.limit() != 0 {
+        return Err(format!(
+            "could not read packet. Packet length is {packet_length}, but the connection only had {} bytes.",
+            packet_length as u64 - input.limit()
+        ));
+    }
+
+    Ok((packet_id, body))
+}
+
 #[cfg(test)]
 mod var_int_tests {
     use super::*;
 
+    #[test]
+    fn test_var_int_bytes() {
+        assert_eq!(var_int_bytes(0), [0x00]);
+        assert_eq!(var_int_bytes(1), [0x01]);
+        assert_eq!(var_int_bytes(300), [0xac, 0x02]);
+        assert_eq!(var_int_bytes(-1), [0xff, 0xff, 0xff, 0xff, 0x0f]);
+    }
+
+    #[test]
+    fn test_var_long_bytes() {
+        assert_eq!(var_long_bytes(0), [0x00]);
+        assert_eq!(var_long_bytes(2147483647), [0xff, 0xff, 0xff, 0xff, 0x07]);
+        assert_eq!(
+            var_long_bytes(-1),
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+        assert_eq!(
+            var_long_bytes(i64::MIN),
+            [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01]
+        );
+    }
+
     #[test]
     fn test_write_var_int_0() {
         const WRITE_VALUE: i32 = 0;
@@ -293,6 +507,156 @@ mod var_int_tests {
     }
 }
 
+#[cfg(test)]
+mod response_tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_response_with_only_description() {
+        let json = r#"{"description": "A Minecraft Server"}"#;
+        let response: Response = serde_json::from_str(json).unwrap();
+        assert!(response.version.is_none());
+        assert!(response.players.is_none());
+        assert!(response.favicon.is_none());
+        assert_eq!(response.description, "A Minecraft Server");
+    }
+
+    #[test]
+    fn test_deserialize_extra_fields() {
+        let json = r#"{"description": "", "previewsChat": false, "chatReportsEnabled": true}"#;
+        let response: Response = serde_json::from_str(json).unwrap();
+        assert_eq!(response.previews_chat, Some(false));
+        assert_eq!(response.extra_fields.len(), 1);
+        assert_eq!(
+            response.extra_fields.get("chatReportsEnabled"),
+            Some(&serde_json::Value::Bool(true))
+        );
+    }
+
+    #[test]
+    fn test_deserialize_version() {
+        let json = r#"{"description": "", "version": {"name": "1.20.4", "protocol": 765}}"#;
+        let response: Response = serde_json::from_str(json).unwrap();
+        let version = response.version.unwrap();
+        assert_eq!(version.name, "1.20.4");
+        assert_eq!(version.protocol, 765);
+        assert!(!version.coerced);
+    }
+
+    #[test]
+    fn test_deserialize_version_with_string_protocol() {
+        let json = r#"{"description": "", "version": {"name": "Velocity", "protocol": "765"}}"#;
+        let response: Response = serde_json::from_str(json).unwrap();
+        let version = response.version.unwrap();
+        assert_eq!(version.name, "Velocity");
+        assert_eq!(version.protocol, 765);
+        assert!(version.coerced);
+    }
+
+    #[test]
+    fn test_deserialize_version_without_name() {
+        let json = r#"{"description": "", "version": {"protocol": 47}}"#;
+        let response: Response = serde_json::from_str(json).unwrap();
+        let version = response.version.unwrap();
+        assert_eq!(version.name, "unknown");
+        assert_eq!(version.protocol, 47);
+        assert!(version.coerced);
+    }
+
+    #[test]
+    fn test_deserialize_version_with_invalid_protocol() {
+        let json = r#"{"description": "", "version": {"name": "Proxy", "protocol": "latest"}}"#;
+        let response: Response = serde_json::from_str(json).unwrap();
+        let version = response.version.unwrap();
+        assert_eq!(version.protocol, -1);
+        assert!(version.coerced);
+    }
+}
+
+#[cfg(test)]
+mod packet_tests {
+    use super::*;
+
+    #[test]
+    fn test_read_packet_bigger_than_maximum_size() {
+        // Claims to be 2 GiB long
+        let mut bytes: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0x07, 0x00];
+        assert_eq!(
+            read_packet(&mut bytes, DEFAULT_MAX_PACKET_SIZE),
+            Err(format!(
+                "Packet length 2147483647 exceeds the maximum packet size of {DEFAULT_MAX_PACKET_SIZE} bytes"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_read_packet_with_custom_maximum_size() {
+        let mut bytes: &[u8] = &[0x03, 0x00, 0xAA, 0xBB];
+        assert!(read_packet(&mut bytes, 2).is_err());
+        let mut bytes: &[u8] = &[0x03, 0x00, 0xAA, 0xBB];
+        assert_eq!(read_packet(&mut bytes, 3), Ok((0, vec![0xAA, 0xBB])));
+    }
+
+    #[test]
+    fn test_write_packet() {
+        let expected = &[0x3, 0x1, 0xAA, 0xBB];
+
+        let mut buffer: Vec<u8> = vec![];
+        assert!(write_packet(&mut buffer, 1, &[0xAA, 0xBB]).is_ok());
+        assert_eq!(buffer, expected);
+    }
+
+    #[test]
+    fn test_write_empty_packet() {
+        let expected = &[0x1, 0x0];
+
+        let mut buffer: Vec<u8> = vec![];
+        assert!(write_packet(&mut buffer, 0, &[]).is_ok());
+        assert_eq!(buffer, expected);
+    }
+
+    #[test]
+    fn test_packet_round_trip() {
+        let body: Vec<u8> = (0..=255).collect();
+
+        let mut buffer: Vec<u8> = vec![];
+        assert!(write_packet(&mut buffer, 0x2A, &body).is_ok());
+        let read_value = read_packet(&mut buffer.as_slice(), DEFAULT_MAX_PACKET_SIZE);
+        assert_eq!(read_value, Ok((0x2A, body)));
+    }
+
+    #[test]
+    fn test_read_packet_does_not_read_past_its_length() {
+        let mut bytes: &[u8] = &[0x2, 0x1, 0xAA, 0xBB];
+        assert_eq!(
+            read_packet(&mut bytes, DEFAULT_MAX_PACKET_SIZE),
+            Ok((1, vec![0xAA]))
+        );
+        assert_eq!(bytes, &[0xBB]);
+    }
+
+    #[test]
+    fn test_read_truncated_packet() {
+        let mut bytes: &[u8] = &[0x5, 0x1, 0xAA];
+        assert!(read_packet(&mut bytes, DEFAULT_MAX_PACKET_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_read_packet_with_negative_length() {
+        let mut bytes: &[u8] = &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F];
+        assert!(read_packet(&mut bytes, DEFAULT_MAX_PACKET_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_read_zero_length_packet() {
+        let mut bytes: &[u8] = &[0x00, 0x00];
+        assert_eq!(
+            read_packet(&mut bytes, DEFAULT_MAX_PACKET_SIZE),
+            Err("The server sent an empty packet".to_owned())
+        );
+    }
+}
+
 #[cfg(test)]
 mod string_tests {
     use super::*;
@@ -317,4 +681,131 @@ mod string_tests {
         assert!(write_string(&mut buffer, string).is_ok());
         assert_eq!(buffer, expected);
     }
+
+    #[test]
+    fn test_read_invalid_utf8_string() {
+        // "Caf\xe9" is "Café" in Latin-1
+        let mut bytes: &[u8] = &[0x4, 0x43, 0x61, 0x66, 0xe9];
+        assert!(read_string(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn test_read_invalid_utf8_string_lossy() {
+        let mut bytes: &[u8] = &[0x4, 0x43, 0x61, 0x66, 0xe9];
+        assert_eq!(read_string_lossy(&mut bytes), Ok("Caf\u{FFFD}".to_owned()));
+        assert!(bytes.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod position_tests {
+    use super::*;
+
+    #[test]
+    fn test_read_position() {
+        // Example from the protocol documentation
+        let input = 0x4607_632C_15B4_833F_u64.to_be_bytes();
+        assert_eq!(
+            read_position(&mut input.as_slice()),
+            Ok((18357644, 831, -20882616))
+        );
+    }
+
+    #[test]
+    fn test_read_negative_position() {
+        let input = (-1i64).to_be_bytes();
+        assert_eq!(read_position(&mut input.as_slice()), Ok((-1, -1, -1)));
+    }
+
+    #[test]
+    fn test_read_position_minimum_values() {
+        let input = 0x8000_0020_0000_0800_u64.to_be_bytes();
+        assert_eq!(
+            read_position(&mut input.as_slice()),
+            Ok((-(1 << 25), -(1 << 11), -(1 << 25)))
+        );
+    }
+
+    #[test]
+    fn test_write_position() {
+        let mut output = vec![];
+        write_position(&mut output, 18357644, 831, -20882616).unwrap();
+        assert_eq!(output, 0x4607_632C_15B4_833F_u64.to_be_bytes());
+    }
+
+    #[test]
+    fn test_write_and_read_negative_position() {
+        let mut output = vec![];
+        write_position(&mut output, -100, -64, 12345).unwrap();
+        assert_eq!(
+            read_position(&mut output.as_slice()),
+            Ok((-100, -64, 12345))
+        );
+    }
+
+    #[test]
+    fn test_write_position_out_of_range() {
+        let mut output = vec![];
+        assert!(write_position(&mut output, 0, 2048, 0).is_err());
+        assert!(output.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod fixed_width_tests {
+    use super::*;
+
+    #[test]
+    fn test_write_long_is_big_endian() {
+        let cases: [(i64, [u8; 8]); 5] = [
+            (0, [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            (1, [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]),
+            (i64::MAX, [0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            (i64::MIN, [0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            (-1, [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+        ];
+        for (value, expected) in cases {
+            let mut output = vec![];
+            write_long(&mut output, value).unwrap();
+            assert_eq!(output, expected, "value {value}");
+        }
+    }
+
+    #[test]
+    fn test_long_round_trip() {
+        for value in [0, 1, i64::MAX, i64::MIN, -1, 0x0102_0304_0506_0708] {
+            let mut output = vec![];
+            write_long(&mut output, value).unwrap();
+            let mut input = output.as_slice();
+            assert_eq!(read_long(&mut input), Ok(value));
+            assert!(input.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_read_long_leaves_following_bytes() {
+        let mut input: &[u8] = &[0, 0, 0, 0, 0, 0, 0x01, 0x02, 0xaa];
+        assert_eq!(read_long(&mut input), Ok(0x0102));
+        assert_eq!(input, [0xaa]);
+    }
+
+    #[test]
+    fn test_read_long_too_short() {
+        let mut input: &[u8] = &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07];
+        assert!(read_long(&mut input).is_err());
+    }
+
+    #[test]
+    fn test_write_unsigned_short_is_big_endian() {
+        let cases: [(u16, [u8; 2]); 3] = [
+            (0, [0x00, 0x00]),
+            (25565, [0x63, 0xdd]),
+            (u16::MAX, [0xff, 0xff]),
+        ];
+        for (value, expected) in cases {
+            let mut output = vec![];
+            write_unsigned_short(&mut output, value).unwrap();
+            assert_eq!(output, expected, "value {value}");
+        }
+    }
 }
@@ -1,30 +1,68 @@
 mod arguments;
+mod capture;
 mod chat;
 mod data_types;
+mod packet_dump;
+mod versions;
 
-use arguments::CommandLineArguments;
+use arguments::{AddressFamily, ColorPolicy, CommandLineArguments};
 use base64::{engine::general_purpose, Engine as _};
+use capture::TeeReader;
 use data_types::*;
+use packet_dump::PacketDumper;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
 use std::process::{ExitCode, Termination};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     env::args,
-    io::{stderr, stdout, BufReader, BufWriter, IsTerminal, Read, Write},
+    fs::{File, OpenOptions},
+    io::{self, stderr, stdout, BufRead, BufReader, BufWriter, IsTerminal, Read, Write},
     net::{Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
-    time::Instant,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
 };
 
-const MIN_MINECRAFT_PROTOCOL_VERSION: i32 = 0;
+const OPEN_TO_LAN_PORT: u16 = 4445;
+// Open to LAN announcements are tiny, but a long MOTD can make them bigger than one would expect
+const LAN_BUFFER_SIZE: usize = 4096;
+// How often the LAN listener wakes up to check whether it was interrupted
+const LAN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const FORMAT_PLACEHOLDERS: &[&str] = &[
+    "version", "protocol", "online", "max", "latency", "motd", "host", "port",
+];
+// Next state requested in the handshake
+const HANDSHAKE_STATUS: i32 = 1;
+const HANDSHAKE_LOGIN: i32 = 2;
+// Protocol of 1.20.2, the first release with a configuration state between login and play
+const FIRST_CONFIGURATION_PROTOCOL: i32 = 764;
 const RESET_COLORS: &str = "\x1B[0m";
+const FG_RED: &str = "\x1B[91m";
+const FG_GREEN: &str = "\x1B[92m";
 const FG_YELLOW: &str = "\x1B[93m";
+const FG_GRAY: &str = "\x1B[90m";
 
 // Error codes based on BSD sysexits (https://man.freebsd.org/cgi/man.cgi?query=sysexits&apropos=0&sektion=0&manpath=FreeBSD+11.2-stable&arch=default&format=html)
+#[derive(PartialEq, Debug)]
 enum ErrorCode {
     Ok = 0,
     IncorrectParameters = 65,
+    CannotOpenInput = 66,
     HostDoesNotExist = 68,
+    Unavailable = 69,
+    CannotCreateFile = 73,
+    IoError = 74,
+    TemporaryFailure = 75,
     Protocol = 76,
+    // Not part of sysexits. The closest one, EX_DATAERR (65), is already taken by incorrect parameters.
+    MalformedJson = 79,
+    // Also outside sysexits. The server answered, but --fail-on-empty or --fail-if-full matched its player count.
+    PlayerCount = 80,
+    // Also outside sysexits. The server answered, but --expect-version or --expect-protocol didn't match its version.
+    UnexpectedVersion = 81,
 }
 
 impl Termination for ErrorCode {
@@ -34,7 +72,7 @@ impl Termination for ErrorCode {
 }
 
 fn main() -> ErrorCode {
-    let arguments = match CommandLineArguments::parse(&mut args()) {
+    let mut arguments = match CommandLineArguments::parse(&mut args()) {
         Ok(args) => args,
         Err(e) => {
             // TODO: Print usage or implement -h flag
@@ -42,466 +80,3472 @@ fn main() -> ErrorCode {
             return ErrorCode::IncorrectParameters;
         }
     };
-    if arguments.open_to_lan {
+    arguments.color_policy =
+        ColorPolicy::resolve(arguments.no_color, |name| std::env::var(name).ok());
+    if arguments.list_colors {
+        list_colors(&arguments)
+    } else if arguments.open_to_lan {
         listen_for_lan_games(&arguments)
+    } else if arguments.tcp_check {
+        check_tcp_connection(&arguments)
+    } else if arguments.login_probe {
+        probe_login(&arguments)
+    } else if !arguments.probe_protocols.is_empty() {
+        probe_protocols(&arguments)
+    } else if let Some(pings) = arguments.scan_motd {
+        scan_motd(&arguments, pings)
     } else {
         ping_server(&arguments)
     }
 }
 
+// Everything we learned from the server after a successful status ping
+struct PingResult {
+    status_response_json: String,
+    server_response: Response,
+    latency: Option<Duration>,
+    pong_payload_matches: bool,
+    timing: Timing,
+}
+
+// How long each phase before the ping took, for --timing. Phases that didn't happen, like resolving the address of a
+// Unix socket, are left empty.
+#[derive(Clone, Copy, Default)]
+struct Timing {
+    resolve: Option<Duration>,
+    connect: Option<Duration>,
+    status: Option<Duration>,
+}
+
 fn ping_server(arguments: &CommandLineArguments) -> ErrorCode {
-    let address = (arguments.host.as_ref(), arguments.port)
-        .to_socket_addrs()
-        .ok()
-        .and_then(|mut addr| addr.next());
-    let address = match address {
-        Some(addr) => addr,
-        None => {
-            eprintln!("Invalid address \'{}\'", arguments.host);
+    // The deadline caps the whole operation, no matter how many phases are left
+    let deadline = arguments.deadline.map(|deadline| Instant::now() + deadline);
+
+    let (mut output, print_colors) = match open_output(arguments) {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Error: Could not open output file");
+            eprintln!("More details: {e}");
+            return ErrorCode::CannotCreateFile;
+        }
+    };
+
+    // Catch mistakes in the template before connecting to the server
+    if let Some(template) = &arguments.format {
+        let known_placeholder = |name: &str| FORMAT_PLACEHOLDERS.contains(&name).then(String::new);
+        if let Err(e) = expand_template(template, known_placeholder) {
+            eprintln!("Error: Invalid format template");
+            eprintln!("More details: {e}");
             return ErrorCode::IncorrectParameters;
         }
+    }
+
+    if arguments.early_hints {
+        return ping_with_early_hints(&mut output, arguments, deadline, print_colors);
+    }
+
+    let ping_result = match arguments.wait_online {
+        Some(timeout) => wait_until_online(arguments, timeout, deadline),
+        None => connect_and_ping_with_fallback(arguments, deadline),
     };
+    let ping_result = match ping_result {
+        Ok(result) => result,
+        Err(error_code) => return error_code,
+    };
+    print_line_verbose("Disconnected", arguments);
 
-    print_line_verbose("Attempting to connect...", arguments);
-    let tcp_connection = match TcpStream::connect(address) {
-        Ok(connection) => connection,
-        Err(_) => {
-            eprintln!("Could not connect to server");
-            return ErrorCode::HostDoesNotExist;
+    // Some servers only show everything to clients on their own protocol, so ask again claiming to be one
+    let reported_protocol = ping_result
+        .server_response
+        .version
+        .as_ref()
+        .map(|version| version.protocol)
+        .filter(|protocol| *protocol > 0 && *protocol != arguments.protocol);
+    let (initial_result, ping_result) = match reported_protocol {
+        Some(protocol) if arguments.match_protocol => {
+            print_line_verbose(
+                format!("Pinging again with the protocol reported by the server: {protocol}")
+                    .as_ref(),
+                arguments,
+            );
+            let matched_arguments = CommandLineArguments {
+                protocol,
+                ..arguments.clone()
+            };
+            match connect_and_ping(&matched_arguments, deadline) {
+                Ok(matched_result) => {
+                    print_line_verbose("Disconnected", arguments);
+                    (Some(ping_result), matched_result)
+                }
+                Err(error_code) => return error_code,
+            }
         }
+        _ => (None, ping_result),
     };
-    let mut buf_reader = BufReader::new(&tcp_connection);
-    let mut buf_writer = BufWriter::new(&tcp_connection);
-    print_line_verbose(
-        format!("Connection established to {}", &arguments.host).as_ref(),
+
+    // Only show the first answer when matching the protocol made a difference
+    if let Some(initial_result) = initial_result
+        .filter(|initial| initial.status_response_json != ping_result.status_response_json)
+    {
+        let result = print_ping_result(&mut output, initial_result, arguments, print_colors)
+            .and_then(|_| writeln!(output));
+        if let Err(e) = result {
+            eprintln!("Error: Could not write output");
+            eprintln!("More details: {e}");
+            return ErrorCode::IoError;
+        }
+    }
+
+    let exit_code = exit_code_after_ping(
+        ping_result.pong_payload_matches,
+        check_status(&ping_result.server_response, arguments),
         arguments,
     );
+    if let Err(e) = print_ping_result(&mut output, ping_result, arguments, print_colors) {
+        eprintln!("Error: Could not write output");
+        eprintln!("More details: {e}");
+        return ErrorCode::IoError;
+    }
 
-    // We need to ensure that we send the hostname (if provided) instead of the IP address because otherwise some servers
-    // may not respond at all
-    match send_handshake(&mut buf_writer, &arguments.host, arguments.port) {
-        Ok(response) => response,
-        Err(e) => {
-            eprintln!("Error: Could not send handshake");
-            eprintln!("More details: {e}");
-            return ErrorCode::Protocol;
+    exit_code
+}
+
+// Every check on the status response that may turn a successful ping into a failure
+fn check_status(server_response: &Response, arguments: &CommandLineArguments) -> ErrorCode {
+    match check_expected_version(server_response.version.as_ref(), arguments) {
+        ErrorCode::Ok => check_player_count(server_response.players.as_ref(), arguments),
+        error_code => error_code,
+    }
+}
+
+// A wrong pong always fails. With --fail-on-mismatch-only it's the only thing that can fail once the server answered.
+fn exit_code_after_ping(
+    pong_payload_matches: bool,
+    status_exit_code: ErrorCode,
+    arguments: &CommandLineArguments,
+) -> ErrorCode {
+    if !pong_payload_matches {
+        ErrorCode::Protocol
+    } else if arguments.fail_on_mismatch_only {
+        ErrorCode::Ok
+    } else {
+        status_exit_code
+    }
+}
+
+// Lets CI check that a rollout landed the right build. The status is still printed.
+fn check_expected_version(
+    version: Option<&ResponseVersion>,
+    arguments: &CommandLineArguments,
+) -> ErrorCode {
+    if arguments.expect_version.is_none() && arguments.expect_protocol.is_none() {
+        return ErrorCode::Ok;
+    }
+    let Some(version) = version else {
+        eprintln!("Error: The server didn't report its version");
+        return ErrorCode::UnexpectedVersion;
+    };
+    if let Some(expected_name) = &arguments.expect_version {
+        if !version.name.contains(expected_name.as_str()) {
+            eprintln!(
+                "Error: Expected a version containing '{expected_name}', but the server reported '{}'",
+                version.name
+            );
+            return ErrorCode::UnexpectedVersion;
         }
+    }
+    if let Some(expected_protocol) = arguments.expect_protocol {
+        if version.protocol != expected_protocol {
+            eprintln!(
+                "Error: Expected protocol {expected_protocol}, but the server reported {}",
+                version.protocol
+            );
+            return ErrorCode::UnexpectedVersion;
+        }
+    }
+    ErrorCode::Ok
+}
+
+// Lets monitoring scripts alert when a server is up, but nobody can or wants to play on it. The status is still printed.
+fn check_player_count(
+    players: Option<&ResponsePlayers>,
+    arguments: &CommandLineArguments,
+) -> ErrorCode {
+    let Some(players) = players else {
+        // Without a player count there's nothing to compare
+        return ErrorCode::Ok;
     };
-    print_line_verbose("Handshake request sent!", arguments);
+    if arguments.fail_on_empty && players.online == 0 {
+        eprintln!("Error: The server is empty");
+        ErrorCode::PlayerCount
+    } else if arguments.fail_if_full && players.online >= players.max {
+        eprintln!("Error: The server is full");
+        ErrorCode::PlayerCount
+    } else {
+        ErrorCode::Ok
+    }
+}
 
-    match send_status_request(&mut buf_writer) {
-        Ok(response) => response,
+// Only checks that something accepts connections on the address, without sending any Minecraft packets
+fn check_tcp_connection(arguments: &CommandLineArguments) -> ErrorCode {
+    let deadline = arguments.deadline.map(|deadline| Instant::now() + deadline);
+
+    let (mut output, _) = match open_output(arguments) {
+        Ok(output) => output,
         Err(e) => {
-            eprintln!("Error: Could not send status request");
+            eprintln!("Error: Could not open output file");
             eprintln!("More details: {e}");
-            return ErrorCode::Protocol;
+            return ErrorCode::CannotCreateFile;
         }
     };
-    print_line_verbose("Status request sent!", arguments);
 
-    let status_response_json = match read_status_response(&mut buf_reader) {
-        Ok(response) => response,
+    let start_time = Instant::now();
+    let connect_time = match connect_and_run(arguments, deadline, |_, _| Ok(start_time.elapsed())) {
+        Ok(connect_time) => connect_time,
+        Err(error_code) => return error_code,
+    };
+    print_line_verbose("Disconnected", arguments);
+
+    if let Err(e) = writeln!(output, "Connected in {} ms", connect_time.as_millis()) {
+        eprintln!("Error: Could not write output");
+        eprintln!("More details: {e}");
+        return ErrorCode::IoError;
+    }
+    ErrorCode::Ok
+}
+
+fn probe_protocols(arguments: &CommandLineArguments) -> ErrorCode {
+    let (mut output, _) = match open_output(arguments) {
+        Ok(output) => output,
         Err(e) => {
-            eprintln!("Error: Could not read status response");
+            eprintln!("Error: Could not open output file");
             eprintln!("More details: {e}");
-            return ErrorCode::Protocol;
+            return ErrorCode::CannotCreateFile;
         }
     };
-    print_line_verbose("Received status response!", arguments);
-    let server_response: Response = match serde_json::from_str(&status_response_json) {
-        Ok(response) => response,
+
+    // The deadline covers every probe together
+    let deadline = arguments.deadline.map(|deadline| Instant::now() + deadline);
+    let mut rows = Vec::with_capacity(arguments.probe_protocols.len());
+    let mut first_error = None;
+    let mut answered_probes = 0;
+    for protocol in &arguments.probe_protocols {
+        if deadline_exceeded(deadline) {
+            eprintln!("Error: Deadline exceeded before probing protocol {protocol}");
+            first_error.get_or_insert(ErrorCode::Protocol);
+            rows.push(format!("{protocol:<10}unavailable"));
+            continue;
+        }
+        // Each probe is an independent status request claiming a different protocol. Latency doesn't matter here.
+        let probe_arguments = CommandLineArguments {
+            protocol: *protocol,
+            no_ping: true,
+            ..arguments.clone()
+        };
+        print_line_verbose(format!("Probing protocol {protocol}").as_ref(), arguments);
+        let row = match connect_and_ping(&probe_arguments, deadline) {
+            Ok(ping_result) => {
+                answered_probes += 1;
+                let version = ping_result.server_response.version.unwrap_or_default();
+                // Forge servers announce their mods in one of these sections depending on the release
+                let has_forge_data =
+                    serde_json::from_str::<serde_json::Value>(&ping_result.status_response_json)
+                        .map(|json| {
+                            json.get("forgeData").is_some() || json.get("modinfo").is_some()
+                        })
+                        .unwrap_or(false);
+                format!(
+                    "{protocol:<10}{:<24}{:<20}{}",
+                    version.name,
+                    version.protocol,
+                    if has_forge_data { "Yes" } else { "No" }
+                )
+            }
+            Err(error_code) => {
+                first_error.get_or_insert(error_code);
+                format!("{protocol:<10}unavailable")
+            }
+        };
+        rows.push(row);
+    }
+
+    let result = writeln!(
+        output,
+        "{:<10}{:<24}{:<20}Forge data",
+        "Protocol", "Version name", "Reported protocol"
+    )
+    .and_then(|_| rows.iter().try_for_each(|row| writeln!(output, "{row}")));
+    if let Err(e) = result {
+        eprintln!("Error: Could not write output");
+        eprintln!("More details: {e}");
+        return ErrorCode::IoError;
+    }
+
+    // One answer is enough to consider the scan successful
+    match first_error {
+        Some(error_code) if answered_probes == 0 => error_code,
+        _ => ErrorCode::Ok,
+    }
+}
+
+fn scan_motd(arguments: &CommandLineArguments, pings: u32) -> ErrorCode {
+    let (mut output, _) = match open_output(arguments) {
+        Ok(output) => output,
         Err(e) => {
-            eprintln!("Error: Could not decode response because it has malformed JSON data");
+            eprintln!("Error: Could not open output file");
             eprintln!("More details: {e}");
-            return ErrorCode::Protocol;
+            return ErrorCode::CannotCreateFile;
         }
     };
 
-    // Calculate server response time
-    let system_time_sec = match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(t) => t.as_secs() as i64,
-        Err(_) => 0,
+    // Servers that rotate their MOTD pick one for every status request, so reconnect each time
+    let scan_arguments = CommandLineArguments {
+        no_ping: true,
+        ..arguments.clone()
     };
-    let start_time = match send_ping_request(&mut buf_writer, system_time_sec) {
-        Ok(time) => time,
-        Err(e) => {
-            eprintln!("Error: Could not send ping request");
-            eprintln!("More details: {e}");
-            return ErrorCode::Protocol;
+    // The deadline covers every ping together
+    let deadline = arguments.deadline.map(|deadline| Instant::now() + deadline);
+    let mut motds = Vec::with_capacity(pings as usize);
+    let mut first_error = None;
+    for ping in 1..=pings {
+        if deadline_exceeded(deadline) {
+            eprintln!(
+                "Error: Deadline exceeded after {} of {pings} pings",
+                ping - 1
+            );
+            first_error.get_or_insert(ErrorCode::Protocol);
+            break;
         }
-    };
-    print_line_verbose("Sent ping request!", arguments);
+        print_line_verbose(format!("Ping {ping} of {pings}").as_ref(), arguments);
+        match connect_and_ping(&scan_arguments, deadline) {
+            Ok(ping_result) => motds.push(chat::parse_chat_object_json_to_string(
+                &ping_result.server_response.description,
+                chat::TextFormat::Plain,
+                chat::ObfuscateMode::Plain,
+            )),
+            Err(error_code) => {
+                first_error.get_or_insert(error_code);
+            }
+        }
+    }
+    if motds.is_empty() {
+        return first_error.unwrap_or(ErrorCode::Unavailable);
+    }
 
-    let payload = match read_pong_response(&mut buf_reader) {
-        Ok(payload) => payload,
+    let answered_pings = motds.len();
+    let motds = unique_in_order(motds);
+    let result = motds
+        .iter()
+        .try_for_each(|motd| writeln!(output, "{motd}\n"))
+        .and_then(|_| {
+            writeln!(
+                output,
+                "Found {} unique MOTDs in {answered_pings} answered pings",
+                motds.len()
+            )
+        });
+    if let Err(e) = result {
+        eprintln!("Error: Could not write output");
+        eprintln!("More details: {e}");
+        return ErrorCode::IoError;
+    }
+    ErrorCode::Ok
+}
+
+// Drops repeated items, keeping the order in which they were first seen
+fn unique_in_order(items: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    items
+        .into_iter()
+        .filter(|item| seen.insert(item.clone()))
+        .collect()
+}
+
+// What the server answered to our Login Start packet
+#[derive(PartialEq, Debug)]
+enum LoginResponse {
+    // The server kicked us, usually with a whitelist, ban or maintenance message
+    Disconnect(String),
+    // The server runs in online mode and wants to authenticate the player
+    EncryptionRequest,
+    // The server runs in offline mode and let us in
+    LoginSuccess,
+    // The server let us in and wants to compress the rest of the connection
+    SetCompression,
+    // A proxy or a mod wants more data before deciding
+    PluginRequest,
+}
+
+fn probe_login(arguments: &CommandLineArguments) -> ErrorCode {
+    let deadline = arguments.deadline.map(|deadline| Instant::now() + deadline);
+
+    let (mut output, print_colors) = match open_output(arguments) {
+        Ok(output) => output,
         Err(e) => {
-            eprintln!("Error: Could not read pong response");
+            eprintln!("Error: Could not open output file");
             eprintln!("More details: {e}");
-            return ErrorCode::Protocol;
+            return ErrorCode::CannotCreateFile;
         }
     };
-    if payload != system_time_sec {
-        eprintln!("Error: the server's pong response is an invalid value: 0x{payload:x}. Sent: 0x{system_time_sec:x}");
-        return ErrorCode::Protocol;
-    }
 
-    let response_elapsed_time = start_time.elapsed();
-    print_line_verbose("Received pong response!", arguments);
-    print_line_verbose(
-        format!("Delay: {} ms", response_elapsed_time.as_millis()).as_ref(),
-        arguments,
-    );
+    let login_response = connect_and_run(arguments, deadline, |mut reader, mut writer| {
+        run_login_probe(&mut reader, &mut writer, arguments, deadline)
+    });
+    let login_response = match login_response {
+        Ok(response) => response,
+        Err(error_code) => return error_code,
+    };
     print_line_verbose("Disconnected", arguments);
 
-    if arguments.get_favicon {
-        // Print decoded favicon to stdout
+    if let Err(e) = print_login_response(&mut output, login_response, arguments, print_colors) {
+        eprintln!("Error: Could not write output");
+        eprintln!("More details: {e}");
+        return ErrorCode::IoError;
+    }
+
+    ErrorCode::Ok
+}
+
+fn print_login_response<T: Write>(
+    output: &mut T,
+    login_response: LoginResponse,
+    arguments: &CommandLineArguments,
+    print_colors: bool,
+) -> io::Result<()> {
+    match login_response {
+        LoginResponse::Disconnect(reason) => {
+            // The reason should be a chat component, but some servers send plain text
+            let reason =
+                serde_json::from_str(&reason).unwrap_or(serde_json::Value::String(reason));
+            let reason = chat::parse_chat_object_json_to_string(
+                &reason,
+                text_format(print_colors),
+                arguments.obfuscate_mode,
+            );
+            writeln!(output, "The server refused the login:")?;
+            if print_colors {
+                writeln!(output, "{reason}{RESET_COLORS}")?;
+            } else {
+                writeln!(output, "{reason}")?;
+            }
+        }
+        LoginResponse::EncryptionRequest => writeln!(
+            output,
+            "The server requested encryption. It runs in online mode, so it only lets authenticated players in"
+        )?,
+        LoginResponse::LoginSuccess => writeln!(
+            output,
+            "The server accepted the login. It runs in offline mode"
+        )?,
+        LoginResponse::SetCompression => writeln!(
+            output,
+            "The server enabled compression, so it accepted the login"
+        )?,
+        LoginResponse::PluginRequest => writeln!(
+            output,
+            "The server sent a login plugin request. It's probably behind a proxy or running mods"
+        )?,
+    }
+    Ok(())
+}
+
+fn print_ping_result<T: Write>(
+    output: &mut T,
+    ping_result: PingResult,
+    arguments: &CommandLineArguments,
+    print_colors: bool,
+) -> io::Result<()> {
+    let PingResult {
+        status_response_json,
+        server_response,
+        latency: response_elapsed_time,
+        timing,
+        ..
+    } = ping_result;
+
+    if arguments.only_latency {
+        // Print the latency alone so scripts can consume it directly. --latency can't be combined with --no-ping, so
+        // it's always measured here.
+        if let Some(latency) = response_elapsed_time {
+            writeln!(output, "{}", latency.as_millis())?;
+        }
+    } else if arguments.get_favicon {
+        // Print decoded favicon
         if let Some(favicon) = server_response.favicon {
-            const FORMAT: &str = "data:image/png;base64,";
             if favicon.is_empty() {
-                print_warning("This server doesn't have a favicon.");
-            } else if favicon.starts_with(FORMAT) {
+                print_warning("This server doesn't have a favicon.", arguments);
+            } else if let Some((image_type, data)) = split_favicon_data_uri(&favicon) {
+                if image_type != "png" {
+                    print_warning(
+                        format!(
+                            "The favicon is a {} image instead of PNG.",
+                            image_type.to_uppercase()
+                        )
+                        .as_ref(),
+                        arguments,
+                    );
+                }
                 if arguments.raw_response {
-                    let _ = stdout().write_all(favicon.as_bytes());
+                    output.write_all(favicon.as_bytes())?;
                 } else {
-                    let mut buf = Vec::with_capacity(favicon.len());
-                    // Delete prefix and decode the image as Base64
-                    let result = favicon
-                        .strip_prefix(FORMAT)
-                        .map(|favicon| favicon.as_bytes())
-                        .map(|favicon| general_purpose::STANDARD.decode_vec(favicon, &mut buf))
-                        .map(|_| stdout().write_all(&buf));
-                    if result.is_none() {
+                    let mut buf = Vec::with_capacity(data.len());
+                    // Decode the image as Base64
+                    let result = general_purpose::STANDARD.decode_vec(data.as_bytes(), &mut buf);
+                    if result.is_err() {
                         eprintln!("Error: Could not decode favicon")
+                    } else {
+                        write_favicon(output, &buf, arguments.favicon_hash)?;
                     }
                 }
+            } else if arguments.favicon_hash {
+                print_warning("Could not decode favicon because it has an unknown format. Hashing it as raw data...", arguments);
+                write_favicon(output, favicon.as_bytes(), true)?;
             } else {
-                print_warning("Could not decode favicon because it has an unknown format. Printing it as raw data...");
-                let _ = stdout().write_all(favicon.as_bytes());
+                print_warning("Could not decode favicon because it has an unknown format. Printing it as raw data...", arguments);
+                output.write_all(favicon.as_bytes())?;
             }
         } else {
-            print_warning("This server doesn't have a favicon.");
+            print_warning("This server doesn't have a favicon.", arguments);
+        }
+    } else if arguments.raw_response && arguments.pretty {
+        // Indent the response for humans. The status was already decoded, but don't lose it if it can't be re-parsed.
+        match serde_json::from_str::<serde_json::Value>(&status_response_json) {
+            Ok(json) => writeln!(output, "{}", serde_json::to_string_pretty(&json)?)?,
+            Err(_) => {
+                print_warning(
+                    "Could not parse the response as JSON. Printing it unmodified...",
+                    arguments,
+                );
+                writeln!(output, "{status_response_json}")?;
+            }
         }
     } else if arguments.raw_response {
         // Print raw response data
-        println!("{status_response_json}");
+        writeln!(output, "{status_response_json}")?;
+    } else if arguments.raw_description {
+        // Print only the MOTD as JSON. Indent it for humans, but keep it in one line for other programs.
+        let pretty = arguments.output.is_none() && stdout().is_terminal();
+        let description = if pretty {
+            serde_json::to_string_pretty(&server_response.description)
+        } else {
+            serde_json::to_string(&server_response.description)
+        };
+        writeln!(output, "{}", description?)?;
+    } else if arguments.compact {
+        // Print everything in a single line
+        let version = server_response.version.unwrap_or_default();
+        let players = server_response.players.unwrap_or_default();
+        let motd = chat::parse_chat_object_json_to_string(
+            &server_response.description,
+            chat::TextFormat::Plain,
+            arguments.obfuscate_mode,
+        );
+        let motd = truncate_text(
+            &single_line_motd(&motd, arguments.clean_motd),
+            arguments.motd_width,
+        );
+        let latency = match response_elapsed_time {
+            Some(latency) => format!("{}ms", latency.as_millis()),
+            None => "-".to_owned(),
+        };
+        writeln!(
+            output,
+            "{host}:{port}  {version}  {online}/{max}  {latency}  {motd}",
+            host = arguments.host,
+            port = arguments.port,
+            version = version.name,
+            online = players.online,
+            max = players.max,
+        )?;
+    } else if let Some(template) = &arguments.format {
+        // Print a single line with the placeholders replaced by the values from the server
+        let version = server_response.version.unwrap_or_default();
+        let players = server_response.players.unwrap_or_default();
+        let motd = chat::parse_chat_object_json_to_string(
+            &server_response.description,
+            chat::TextFormat::Plain,
+            arguments.obfuscate_mode,
+        );
+        let latency = match response_elapsed_time {
+            Some(latency) => latency.as_millis().to_string(),
+            None => "unavailable".to_owned(),
+        };
+        let value = |name: &str| match name {
+            "version" => Some(version.name.clone()),
+            "protocol" => Some(version.protocol.to_string()),
+            "online" => Some(players.online.to_string()),
+            "max" => Some(players.max.to_string()),
+            "latency" => Some(latency.clone()),
+            "motd" => Some(single_line_motd(&motd, arguments.clean_motd)),
+            "host" => Some(arguments.host.clone()),
+            "port" => Some(arguments.port.to_string()),
+            _ => None,
+        };
+        let line = expand_template(template, value)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        writeln!(output, "{line}")?;
     } else {
         // Parse status response JSON and print data
-        let apply_font_styles = can_print_colors(&std::io::stdout());
-        let server_description = chat::parse_chat_object_json_to_string(&server_response.description, apply_font_styles);
-        println!("{server_description}");
-        println!("{:<24} {}", "Server version", server_response.version.name);
-        println!("{:<24} {}", "Protocol", server_response.version.protocol);
-        println!(
-            "{:<24} {current}/{max}",
-            "Players",
-            current = server_response.players.online,
-            max = server_response.players.max
+        let server_description = chat::parse_chat_object_json_to_string(
+            &server_response.description,
+            text_format(print_colors),
+            arguments.obfuscate_mode,
         );
+        // Reset styles after the MOTD in case some lines were left out, so they don't leak into the table below
+        let server_description = clamp_lines(&server_description, arguments.motd_lines);
+        let reset_styles = if print_colors { RESET_COLORS } else { "" };
+        writeln!(output, "{server_description}{reset_styles}")?;
 
-        let favicon = if let Some(f) = server_response.favicon {
-            if f.is_empty() {
-                "(No data available)"
-            } else {
-                "(Base64 data)"
+        let version = server_response.version.unwrap_or_else(|| {
+            print_warning("The server didn't report its version.", arguments);
+            ResponseVersion::default()
+        });
+        let players = server_response.players.unwrap_or_else(|| {
+            print_warning("The server didn't report its player count.", arguments);
+            ResponsePlayers::default()
+        });
+        // Collect every row first so the labels can be aligned to the longest one
+        let mut rows = vec![("Server version", version.name)];
+        let protocol = match versions::versions_for_protocol(version.protocol) {
+            Some(versions) if arguments.named_protocol => {
+                format!("{} ({versions})", version.protocol)
             }
-        } else {
-            "(No data available)"
+            _ => version.protocol.to_string(),
         };
-        println!("{:<24} {favicon}", "Favicon");
+        rows.push(("Protocol", protocol));
+        rows.push(("Players", format!("{}/{}", players.online, players.max)));
 
-        let enforces_secure_chat = if server_response.enforces_secure_chat.unwrap_or(false) {
-            "Yes"
-        } else {
-            "No"
-        };
-        println!("{:<24} {enforces_secure_chat}", "Enforces secure chat");
-
-        let previews_chat = if server_response.previews_chat.unwrap_or(false) {
-            "Yes"
-        } else {
-            "No"
+        let favicon = match server_response.favicon.as_deref() {
+            None | Some("") => "(No data available)".to_owned(),
+            // Vanilla servers always send PNG, but some servers send other types of images
+            Some(favicon) => match split_favicon_data_uri(favicon) {
+                Some((image_type, data)) => {
+                    let image_type = if image_type == "png" {
+                        String::new()
+                    } else {
+                        format!(" {}", image_type.to_uppercase())
+                    };
+                    // Show how big the image is once decoded, as long as it can be decoded at all
+                    match general_purpose::STANDARD.decode(data) {
+                        Ok(image) => {
+                            format!(
+                                "(Base64{image_type} data, {})",
+                                format_byte_size(image.len())
+                            )
+                        }
+                        Err(_) => format!("(Base64{image_type} data)"),
+                    }
+                }
+                None => "(Base64 data)".to_owned(),
+            },
         };
-        println!("{:<24} {previews_chat}", "Previews chat");
+        rows.push(("Favicon", favicon));
 
-        println!(
-            "{:<24} {} ms",
-            "Server latency",
-            response_elapsed_time.as_millis()
-        );
-    }
+        let enforces_secure_chat =
+            style_optional_bool(server_response.enforces_secure_chat, print_colors);
+        rows.push(("Enforces secure chat", enforces_secure_chat));
 
-    ErrorCode::Ok
-}
+        let previews_chat = style_optional_bool(server_response.previews_chat, print_colors);
+        rows.push(("Previews chat", previews_chat));
 
-fn send_handshake<T: Write>(output: &mut T, server_address: &str, port: u16) -> Result<(), String> {
-    let mut buffer: Vec<u8> = Vec::with_capacity(4096);
+        // With --early-hints the latency is printed on its own once the pong arrives
+        if !arguments.early_hints {
+            rows.push(("Server latency", format_latency(response_elapsed_time)));
+        }
+        if arguments.describe {
+            describe_rows(&mut rows);
+        }
+        write_table(output, &rows)?;
 
-    // Packet ID
-    write_var_int(&mut buffer, 0)?;
+        if arguments.timing {
+            writeln!(output, "Timing:")?;
+            write_table(output, &timing_rows(&timing, response_elapsed_time))?;
+        }
+        if arguments.verbose {
+            write_extra_fields(output, &server_response.extra_fields)?;
+        }
+    }
 
-    // Protocol version
-    write_var_int(&mut buffer, MIN_MINECRAFT_PROTOCOL_VERSION)?;
+    Ok(())
+}
 
-    // Server address
-    write_string(&mut buffer, server_address)?;
+fn format_latency(latency: Option<Duration>) -> String {
+    match latency {
+        Some(latency) => format!("{} ms", latency.as_millis()),
+        None => "unavailable".to_owned(),
+    }
+}
 
-    // Server port
-    write_unsigned_short(&mut buffer, port)?;
+fn describe_rows(rows: &mut [(&str, String)]) {
+    for (label, value) in rows {
+        if let Some(description) = field_description(label) {
+            value.push_str(" - ");
+            value.push_str(description);
+        }
+    }
+}
 
-    // Next state
-    write_var_int(&mut buffer, 1)?;
+fn timing_rows(timing: &Timing, latency: Option<Duration>) -> Vec<(&'static str, String)> {
+    let format_phase = |phase: Option<Duration>| match phase {
+        Some(duration) => format!("{:.1} ms", duration.as_secs_f64() * 1000.0),
+        None => "unavailable".to_owned(),
+    };
+    vec![
+        ("  DNS resolution", format_phase(timing.resolve)),
+        ("  TCP connection", format_phase(timing.connect)),
+        ("  Status response", format_phase(timing.status)),
+        ("  Ping round trip", format_phase(latency)),
+    ]
+}
 
-    // Packet length
-    let packet_size = buffer.len();
-    write_var_int(output, packet_size as i32)?;
+// Top-level fields of the status response that we don't know about, in alphabetical order
+fn write_extra_fields<T: Write>(
+    output: &mut T,
+    extra_fields: &HashMap<String, serde_json::Value>,
+) -> io::Result<()> {
+    if extra_fields.is_empty() {
+        return Ok(());
+    }
 
-    output.write_all(&buffer).map_err(|e| e.to_string())?;
-    output.flush().map_err(|e| e.to_string())?;
+    let mut names: Vec<&String> = extra_fields.keys().collect();
+    names.sort();
+    writeln!(output, "Extra fields:")?;
+    for name in names {
+        writeln!(output, "  {name}: {}", extra_fields[name])?;
+    }
     Ok(())
 }
 
-fn send_status_request<T: Write>(output: &mut T) -> Result<(), String> {
-    // Packet length
-    write_var_int(output, 1)?; // Packet size should be one byte...
+// Short explanation of every field of the status table, for --describe
+fn field_description(label: &str) -> Option<&'static str> {
+    match label {
+        "Server version" => Some("name of the release or server software the server runs"),
+        "Protocol" => {
+            Some("network protocol version. Clients must use the same one to join the server")
+        }
+        "Players" => Some("players online now and the maximum the server accepts"),
+        "Favicon" => Some("icon shown in the multiplayer server list. Use -f to get it"),
+        "Enforces secure chat" => {
+            Some("whether players must sign their chat messages, which requires a Microsoft account")
+        }
+        "Previews chat" => {
+            Some("whether the server shows a preview of chat messages before sending them (1.19 to 1.19.2)")
+        }
+        "Server latency" => Some("time the server took to answer the ping request"),
+        _ => None,
+    }
+}
 
-    // Packet ID
-    write_var_int(output, 0)?; // ...because zero is represented as one byte for a VarInt
-    output.flush().map_err(|e| e.to_string())?;
+// Prints one "label value" row per line with the values lined up. Labels get at least 24 columns so the table looks
+// the same as always, and the column grows when a label doesn't fit.
+fn write_table<T: Write>(output: &mut T, rows: &[(&str, String)]) -> io::Result<()> {
+    const MIN_LABEL_WIDTH: usize = 24;
+    let width = rows
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(MIN_LABEL_WIDTH);
+    for (label, value) in rows {
+        writeln!(output, "{label:<width$} {value}")?;
+    }
     Ok(())
 }
 
-fn send_ping_request<T: Write>(output: &mut T, payload: i64) -> Result<Instant, String> {
-    // Packet length
-    write_var_int(output, 9)?; // 1 + 8 bytes
-
-    // Packet ID
-    write_var_int(output, 1)?; // Should be one byte
-
-    // Payload
-    write_long(output, payload)?; // Should be 8 bytes
-    output.flush().map_err(|e| e.to_string())?;
-
-    Ok(Instant::now())
+// Formats a size in bytes using binary units, e.g. "4.2 KiB"
+fn format_byte_size(bytes: usize) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
 }
 
-fn read_status_response<T: Read>(input: &mut T) -> Result<String, String> {
-    // Packet length
-    let packet_length = read_var_int(input)?;
-    if packet_length < 0 {
-        return Err(format!("Invalid packet length: {packet_length}"));
+// Splits a "data:image/<type>;base64,<data>" URI into the image type and the Base64 data
+fn split_favicon_data_uri(favicon: &str) -> Option<(&str, &str)> {
+    let (media_type, data) = favicon.strip_prefix("data:image/")?.split_once(',')?;
+    let image_type = media_type.strip_suffix(";base64")?;
+    if image_type.is_empty() || image_type.contains(';') {
+        return None;
     }
+    Some((image_type, data))
+}
 
-    // Here we will ensure that we don't read more than **packet_length** bytes for this packet
-    let mut input = input.take(packet_length as u64);
-
-    // Packet ID
-    let packet_id = read_var_int(&mut input)?;
-    if packet_id != 0 {
-        return Err(format!(
-            "Error: The server responded with an unknown packet ID: 0x{packet_id:x}"
-        ));
+fn write_favicon<T: Write>(output: &mut T, favicon: &[u8], print_hash: bool) -> io::Result<()> {
+    if print_hash {
+        // A checksum is enough to notice when the favicon changes between runs
+        writeln!(output, "{:08x}", crc32(favicon))
+    } else {
+        output.write_all(favicon)
     }
+}
 
-    // JSON response
-    let server_info = read_string(&mut input);
+// CRC-32 as used by zlib and PNG
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
 
-    // Check if all bytes were read successfully
-    let bytes_left = input.bytes().count();
-    if bytes_left != 0 {
-        return Err(format!("ERROR: could not deserialize packet. Packet length is {packet_length}, but we only processed {} bytes.", packet_length - bytes_left as i32));
+fn format_optional_bool(value: Option<bool>) -> &'static str {
+    // Servers that don't know about a field don't send it, which is different from an explicit "false"
+    match value {
+        Some(true) => "Yes",
+        Some(false) => "No",
+        None => "Unknown",
     }
+}
 
-    server_info
+// Styles of chat components follow the colors of the output
+fn text_format(print_colors: bool) -> chat::TextFormat {
+    if print_colors {
+        chat::TextFormat::Ansi
+    } else {
+        chat::TextFormat::Plain
+    }
 }
 
-fn read_pong_response<T: Read>(input: &mut T) -> Result<i64, String> {
-    // Packet length
-    let packet_length = read_var_int(input)?;
-    if packet_length < 0 {
-        return Err(format!("Invalid packet length: {}", packet_length));
+fn style_optional_bool(value: Option<bool>, print_colors: bool) -> String {
+    // Only the value is colored, so the table stays aligned
+    let text = format_optional_bool(value);
+    match value {
+        Some(true) if print_colors => format!("{FG_GREEN}{text}{RESET_COLORS}"),
+        Some(false) if print_colors => format!("{FG_RED}{text}{RESET_COLORS}"),
+        _ => text.to_owned(),
     }
+}
 
-    // Here we will ensure that we don't read more than **packet_length** bytes for this packet
-    let mut input = input.take(packet_length as u64);
+fn clamp_lines(text: &str, max_lines: Option<usize>) -> String {
+    // Keep every line intact, but drop the ones that exceed **max_lines**
+    let lines: Vec<&str> = text.lines().take(max_lines.unwrap_or(usize::MAX)).collect();
+    lines.join("\n")
+}
 
-    // Packet ID
-    let packet_id = read_var_int(&mut input)?;
-    if packet_id != 1 {
-        return Err(format!(
-            "Error: The server responded with an unknown packet ID: 0x{packet_id:x}"
-        ));
+fn single_line_motd(motd: &str, clean: bool) -> String {
+    if clean {
+        clean_motd(motd)
+    } else {
+        motd.replace('\n', " ")
     }
+}
 
-    // Payload
-    let payload = read_long(&mut input)?;
+fn clean_motd(motd: &str) -> String {
+    // Line breaks and tabs become spaces, any other control character is dropped and runs of spaces are collapsed. The
+    // padding servers use to center their MOTD goes away too.
+    let text: String = motd
+        .chars()
+        .filter(|c| c.is_whitespace() || !c.is_control())
+        .collect();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
 
-    // Check if all bytes were read successfully
-    let bytes_left = input.bytes().count();
-    if bytes_left != 0 {
-        return Err(format!("ERROR: could not deserialize packet. Packet length is {packet_length}, but we only processed {} bytes.", packet_length - bytes_left as i32));
+fn truncate_text(text: &str, width: usize) -> String {
+    // Cut the text to at most **width** characters, using an ellipsis to show that it was truncated
+    const ELLIPSIS: &str = "...";
+    if text.chars().count() <= width {
+        text.to_owned()
+    } else if width <= ELLIPSIS.len() {
+        text.chars().take(width).collect()
+    } else {
+        let mut truncated: String = text.chars().take(width - ELLIPSIS.len()).collect();
+        truncated.push_str(ELLIPSIS);
+        truncated
     }
+}
 
-    Ok(payload)
+fn expand_template<F: Fn(&str) -> Option<String>>(
+    template: &str,
+    value: F,
+) -> Result<String, String> {
+    // Replace every {placeholder} with its value. Use {{ and }} to print literal braces.
+    let mut expanded = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                expanded.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                expanded.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let end = rest
+                    .find('}')
+                    .ok_or(format!("Unterminated placeholder \'{{{rest}\'"))?;
+                let name = &rest[..end];
+                let replacement =
+                    value(name).ok_or(format!("Unknown placeholder \'{{{name}}}\'"))?;
+                expanded.push_str(&replacement);
+                chars = rest[end + 1..].chars();
+            }
+            c => expanded.push(c),
+        }
+    }
+    Ok(expanded)
 }
 
-fn listen_for_lan_games(arguments: &CommandLineArguments) -> ErrorCode {
-    // Listen for Open to LAN games. Only Ipv4 sockets are supported.
-    let bind_address = SocketAddr::from(([0, 0, 0, 0], 4445));
-    let ip = bind_address.ip().to_string();
-    let port = bind_address.port().to_string();
-    print_line_verbose(
-        format!("Attempting to bind to {ip}:{port}").as_ref(),
+fn connect_and_ping(
+    arguments: &CommandLineArguments,
+    deadline: Option<Instant>,
+) -> Result<PingResult, ErrorCode> {
+    let mut timing = Timing::default();
+    let result = connect_and_run_timed(
         arguments,
+        deadline,
+        &mut timing,
+        |mut reader, mut writer| run_status_ping(&mut reader, &mut writer, arguments, deadline),
     );
-    let socket = match UdpSocket::bind(bind_address) {
-        Ok(socket) => socket,
-        Err(e) => {
-            eprintln!("Error: Could not bind socket to {ip}:{port}");
-            eprintln!("More details: {e}");
-            return ErrorCode::Protocol;
+    result.map(|ping_result| PingResult {
+        timing: Timing {
+            status: ping_result.timing.status,
+            ..timing
+        },
+        ..ping_result
+    })
+}
+
+fn connect_and_ping_with_fallback(
+    arguments: &CommandLineArguments,
+    deadline: Option<Instant>,
+) -> Result<PingResult, ErrorCode> {
+    // Some servers refuse the default protocol 0 and close the connection instead of answering. Give them a second
+    // chance with a real protocol number.
+    let use_fallback = arguments.protocol_fallback
+        && arguments.protocol == versions::MIN_MINECRAFT_PROTOCOL_VERSION;
+    match connect_and_ping(arguments, deadline) {
+        Err(ErrorCode::Protocol) if use_fallback => {
+            let protocol = versions::latest_protocol_version();
+            print_line_verbose(
+                format!(
+                    "The status request failed with protocol {}. Trying again with protocol {protocol}",
+                    arguments.protocol
+                )
+                .as_ref(),
+                arguments,
+            );
+            let fallback_arguments = CommandLineArguments {
+                protocol,
+                ..arguments.clone()
+            };
+            connect_and_ping(&fallback_arguments, deadline)
+        }
+        result => result,
+    }
+}
+
+fn wait_until_online(
+    arguments: &CommandLineArguments,
+    timeout: Duration,
+    deadline: Option<Instant>,
+) -> Result<PingResult, ErrorCode> {
+    // Keep pinging until the server answers. --deadline caps all the attempts together, just like a single ping.
+    let give_up_time = Instant::now() + timeout;
+    let mut attempt = 1;
+    loop {
+        let result = connect_and_ping_with_fallback(arguments, deadline);
+        let next_attempt_time = Instant::now() + arguments.wait_interval;
+        match result {
+            Ok(result) => return Ok(result),
+            // Trying again won't fix bad arguments
+            Err(ErrorCode::IncorrectParameters) => return Err(ErrorCode::IncorrectParameters),
+            Err(_) if deadline.is_some_and(|deadline| next_attempt_time >= deadline) => {
+                eprintln!("Error: Deadline exceeded while waiting for the server to come online");
+                return Err(ErrorCode::Protocol);
+            }
+            Err(_) if next_attempt_time < give_up_time => {
+                print_line_verbose(
+                    format!(
+                        "The server isn't online yet (attempt {attempt}). Trying again in {} ms...",
+                        arguments.wait_interval.as_millis()
+                    )
+                    .as_ref(),
+                    arguments,
+                );
+                thread::sleep(arguments.wait_interval);
+                attempt += 1;
+            }
+            Err(_) => {
+                eprintln!(
+                    "Error: The server didn't come online within {} seconds",
+                    timeout.as_secs_f64()
+                );
+                return Err(ErrorCode::TemporaryFailure);
+            }
+        }
+    }
+}
+
+// Connects to the server and hands the buffered connection over to the given exchange
+fn connect_and_run<T, F: FnOnce(&mut dyn Read, &mut dyn Write) -> Result<T, ErrorCode>>(
+    arguments: &CommandLineArguments,
+    deadline: Option<Instant>,
+    exchange: F,
+) -> Result<T, ErrorCode> {
+    connect_and_run_timed(arguments, deadline, &mut Timing::default(), exchange)
+}
+
+// Same as connect_and_run, but also records how long resolving the address and connecting took
+fn connect_and_run_timed<T, F: FnOnce(&mut dyn Read, &mut dyn Write) -> Result<T, ErrorCode>>(
+    arguments: &CommandLineArguments,
+    deadline: Option<Instant>,
+    timing: &mut Timing,
+    exchange: F,
+) -> Result<T, ErrorCode> {
+    if let Some(path) = &arguments.unix_socket {
+        return connect_unix_and_run(path, arguments, deadline, exchange);
+    }
+    if let Some(path) = &arguments.replay {
+        return replay_and_run(path, arguments, exchange);
+    }
+
+    let resolve_start = Instant::now();
+    let addresses = match (arguments.host.as_ref(), arguments.port).to_socket_addrs() {
+        Ok(addresses) => addresses,
+        Err(_) => {
+            eprintln!("Invalid address \'{}\'", arguments.host);
+            return Err(ErrorCode::HostDoesNotExist);
         }
     };
-    print_line_verbose("Socket bind successful", arguments);
+    let address = match pick_address(addresses, arguments.address_family) {
+        Some(addr) => addr,
+        None => {
+            let family = match arguments.address_family {
+                AddressFamily::Ipv4 => "IPv4 ",
+                AddressFamily::Ipv6 => "IPv6 ",
+                AddressFamily::Any => "",
+            };
+            eprintln!(
+                "Invalid address \'{}\': no {family}address found",
+                arguments.host
+            );
+            return Err(ErrorCode::HostDoesNotExist);
+        }
+    };
+    timing.resolve = Some(resolve_start.elapsed());
+    if deadline_exceeded(deadline) {
+        eprintln!("Error: Deadline exceeded while resolving the address");
+        return Err(ErrorCode::Protocol);
+    }
 
-    let multicast_group = Ipv4Addr::from([224, 0, 2, 60]);
-    let any_interface = Ipv4Addr::from([0, 0, 0, 0]);
+    print_line_verbose("Attempting to connect...", arguments);
+    let connect_start = Instant::now();
+    let tcp_connection = match deadline {
+        Some(deadline) => {
+            // A zero timeout is rejected as invalid input, which would look like the server being down
+            let Some(remaining_time) = remaining_time(deadline) else {
+                eprintln!("Error: Deadline exceeded while connecting to the server");
+                return Err(ErrorCode::Protocol);
+            };
+            TcpStream::connect_timeout(&address, remaining_time).and_then(|connection| {
+                // Don't let a single read or write block past the deadline
+                connection.set_read_timeout(Some(remaining_time))?;
+                connection.set_write_timeout(Some(remaining_time))?;
+                Ok(connection)
+            })
+        }
+        None => TcpStream::connect(address),
+    };
+    let tcp_connection = match tcp_connection {
+        Ok(connection) => connection,
+        Err(_) => {
+            eprintln!("Could not connect to server");
+            return Err(ErrorCode::Unavailable);
+        }
+    };
+    timing.connect = Some(connect_start.elapsed());
+    if deadline_exceeded(deadline) {
+        eprintln!("Error: Deadline exceeded while connecting to the server");
+        return Err(ErrorCode::Protocol);
+    }
+    let buf_reader = BufReader::new(&tcp_connection);
+    let buf_writer = BufWriter::new(&tcp_connection);
     print_line_verbose(
-        format!("Attempting to join multicast {multicast_group}").as_ref(),
+        format!("Connection established to {}", &arguments.host).as_ref(),
         arguments,
     );
-    if let Err(e) = socket.join_multicast_v4(&multicast_group, &any_interface) {
-        let multicast_group_ip = multicast_group.to_string();
-        eprintln!("Error: Could not join multicast {multicast_group_ip}");
-        eprintln!("More details: {e}");
-        return ErrorCode::Protocol;
+
+    run_exchange(buf_reader, buf_writer, arguments, exchange)
+}
+
+#[cfg(unix)]
+fn connect_unix_and_run<T, F: FnOnce(&mut dyn Read, &mut dyn Write) -> Result<T, ErrorCode>>(
+    path: &Path,
+    arguments: &CommandLineArguments,
+    deadline: Option<Instant>,
+    exchange: F,
+) -> Result<T, ErrorCode> {
+    let path_name = path.display();
+    print_line_verbose(
+        format!("Attempting to connect to Unix socket {path_name}...").as_ref(),
+        arguments,
+    );
+    let remaining_time = match deadline.map(remaining_time) {
+        Some(None) => {
+            eprintln!("Error: Deadline exceeded while connecting to the server");
+            return Err(ErrorCode::Protocol);
+        }
+        Some(remaining_time) => remaining_time,
+        None => None,
+    };
+    let connection = UnixStream::connect(path).and_then(|connection| {
+        // Don't let a single read or write block past the deadline
+        connection.set_read_timeout(remaining_time)?;
+        connection.set_write_timeout(remaining_time)?;
+        Ok(connection)
+    });
+    let connection = match connection {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("Could not connect to Unix socket {path_name}");
+            eprintln!("More details: {e}");
+            return Err(ErrorCode::Unavailable);
+        }
+    };
+    if deadline_exceeded(deadline) {
+        eprintln!("Error: Deadline exceeded while connecting to the server");
+        return Err(ErrorCode::Protocol);
     }
-    print_line_verbose("Joined multicast grop successfully", arguments);
+    let buf_reader = BufReader::new(&connection);
+    let buf_writer = BufWriter::new(&connection);
+    print_line_verbose(
+        format!("Connection established to {path_name}").as_ref(),
+        arguments,
+    );
 
-    print_line_verbose("Listening for incoming packets...", arguments);
-    let mut unique_lan_servers = HashSet::new();
-    let mut buffer = [0; 2048];
-    loop {
-        match socket.recv_from(&mut buffer) {
-            Ok((packet_length, origin_socket)) => {
-                let origin_socket_ip = origin_socket.ip().to_string();
-                let origin_socket_port = origin_socket.port().to_string();
+    run_exchange(buf_reader, buf_writer, arguments, exchange)
+}
 
-                // Parse received data. I refuse to use regular expressions because the format of the message is too simple
-                // to bother adding another dependency.
-                let buffer_portion: Vec<u8> = buffer.iter().cloned().take(packet_length).collect();
-                let message = match String::from_utf8(buffer_portion) {
-                    Ok(s) => s,
-                    Err(_) => {
-                        // Invalid format. Skip this packet.
-                        print_line_verbose(format!("Ignored packet from {origin_socket_ip}:{origin_socket_port} because the format is not valid").as_ref(), arguments);
-                        continue;
-                    }
-                };
+// Picks the first resolved address of the requested family
+fn pick_address(
+    mut addresses: impl Iterator<Item = SocketAddr>,
+    family: AddressFamily,
+) -> Option<SocketAddr> {
+    addresses.find(|address| match family {
+        AddressFamily::Any => true,
+        AddressFamily::Ipv4 => address.is_ipv4(),
+        AddressFamily::Ipv6 => address.is_ipv6(),
+    })
+}
 
-                if let Some((motd, port)) = parse_open_to_lan_message(&message) {
-                    // Cache known servers as long as we keep listening for LAN games
-                    if unique_lan_servers.insert(message.clone()) {
-                        // Server wasn't cached. Print it only once and ignore further Open to LAN messages from this server
-                        if arguments.raw_response {
-                            println!("{message}");
-                        } else {
-                            let with_styles = can_print_colors(&std::io::stdout());
-                            let styled_motd = chat::parse_styles_to_string(motd, with_styles);
-                            println!("[{origin_socket_ip}:{port}]\t{styled_motd}");
-                        }
-                    } else if arguments.verbose {
-                        print_line_verbose(format!("Ignored packet from {origin_socket_ip}:{origin_socket_port} because this server is already known").as_ref(), arguments);
-                    }
+// Feeds the exchange with bytes saved with --capture instead of a live connection. Whatever we send is discarded.
+fn replay_and_run<T, F: FnOnce(&mut dyn Read, &mut dyn Write) -> Result<T, ErrorCode>>(
+    path: &Path,
+    arguments: &CommandLineArguments,
+    exchange: F,
+) -> Result<T, ErrorCode> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error: Could not open replay file {}", path.display());
+            eprintln!("More details: {e}");
+            return Err(ErrorCode::CannotOpenInput);
+        }
+    };
+    print_line_verbose(
+        format!("Replaying server bytes from {}", path.display()).as_ref(),
+        arguments,
+    );
 
-                    /*if let Err(e) = socket.leave_multicast_v4(&multicast_group, &any_interface) {
-                        print_warning(format!("There was an error when attempting to leave multicast group {multicast_group}").as_ref());
-                        eprintln!("More details: {e}");
-                        return ErrorCode::Protocol;
-                    } else {
-                        print_line_verbose(
-                            format!("Left multicast group {multicast_group}").as_ref(),
-                            arguments,
-                        );
-                    }*/
+    run_exchange(BufReader::new(file), io::sink(), arguments, exchange)
+}
 
-                } else {
-                    print_line_verbose(format!("Ignored packet from {origin_socket_ip}:{origin_socket_port} because the format is not valid").as_ref(), arguments);
-                }
-            }
+fn run_exchange<T, F: FnOnce(&mut dyn Read, &mut dyn Write) -> Result<T, ErrorCode>>(
+    reader: impl Read,
+    writer: impl Write,
+    arguments: &CommandLineArguments,
+    exchange: F,
+) -> Result<T, ErrorCode> {
+    let capture_file = match &arguments.capture {
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(file),
             Err(e) => {
-                eprintln!("Error: I/O error when reading incoming data from a multicast socket");
+                eprintln!("Error: Could not create capture file");
                 eprintln!("More details: {e}");
-                return ErrorCode::Protocol;
+                return Err(ErrorCode::CannotCreateFile);
             }
-        }
+        },
+        None => None,
+    };
+
+    let mut reader: Box<dyn Read + '_> = Box::new(reader);
+    let mut writer: Box<dyn Write + '_> = Box::new(writer);
+    if let Some(capture_file) = &capture_file {
+        reader = Box::new(TeeReader::new(reader, capture_file));
+    }
+    if arguments.dump_packets {
+        reader = Box::new(PacketDumper::new(reader, "Received"));
+        writer = Box::new(PacketDumper::new(writer, "Sent"));
+    }
+    let result = exchange(&mut reader, &mut writer);
+    drop(reader);
+
+    if let (Some(capture_file), Some(path)) = (&capture_file, &arguments.capture) {
+        let captured_bytes = capture_file
+            .metadata()
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        print_line_verbose(
+            format!("Captured {captured_bytes} bytes to {}", path.display()).as_ref(),
+            arguments,
+        );
     }
+    result
 }
 
-fn parse_open_to_lan_message(message: &str) -> Option<(&str, &str)> {
-    if message.starts_with("[MOTD]") && message.ends_with("[/AD]") {
-        // Remove [MOTD] and [/AD] from the message
-        let i_start = "[MOTD]".len();
-        let i_end = message.len() - "[/AD]".len();
-        let trimmed_message = &message[i_start..i_end];
+#[cfg(not(unix))]
+fn connect_unix_and_run<T, F: FnOnce(&mut dyn Read, &mut dyn Write) -> Result<T, ErrorCode>>(
+    _path: &Path,
+    _arguments: &CommandLineArguments,
+    _deadline: Option<Instant>,
+    _exchange: F,
+) -> Result<T, ErrorCode> {
+    eprintln!("Error: Unix domain sockets are only supported on Unix platforms");
+    Err(ErrorCode::IncorrectParameters)
+}
 
-        let mut split = trimmed_message.split("[/MOTD][AD]");
-        let motd = match split.next() {
-            Some(motd) => motd,
-            None => {
-                // Invalid format. Skip this packet.
-                return None;
+fn run_status_ping<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    arguments: &CommandLineArguments,
+    deadline: Option<Instant>,
+) -> Result<PingResult, ErrorCode> {
+    let status = request_status(reader, writer, arguments, deadline)?;
+    if should_skip_ping(arguments) {
+        return Ok(status);
+    }
+
+    let (latency, pong_payload_matches) = run_ping(reader, writer, arguments, deadline)?;
+    Ok(PingResult {
+        latency: Some(latency),
+        pong_payload_matches,
+        ..status
+    })
+}
+
+// Handshake, status request and status response. The result doesn't have a latency yet.
+fn request_status<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    arguments: &CommandLineArguments,
+    deadline: Option<Instant>,
+) -> Result<PingResult, ErrorCode> {
+    let status_start = Instant::now();
+    start_handshake(writer, arguments, HANDSHAKE_STATUS)?;
+
+    match send_status_request(writer) {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("Error: Could not send status request");
+            eprintln!("More details: {e}");
+            return Err(ErrorCode::Protocol);
+        }
+    };
+    print_line_verbose("Status request sent!", arguments);
+    if deadline_exceeded(deadline) {
+        eprintln!("Error: Deadline exceeded while sending the status request");
+        return Err(ErrorCode::Protocol);
+    }
+
+    let status_response_json =
+        match read_status_response(reader, arguments.max_packet_size, arguments.lossy) {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("Error: Could not read status response");
+                eprintln!("More details: {e}");
+                return Err(ErrorCode::Protocol);
             }
         };
-        let port = match split.next() {
-            Some(port) => port,
-            None => {
-                // Invalid format. Skip this packet.
-                return None;
+    let timing = Timing {
+        status: Some(status_start.elapsed()),
+        ..Timing::default()
+    };
+    print_line_verbose("Received status response!", arguments);
+    if deadline_exceeded(deadline) {
+        eprintln!("Error: Deadline exceeded while reading the status response");
+        return Err(ErrorCode::Protocol);
+    }
+    let server_response: Response = match serde_json::from_str(&status_response_json) {
+        Ok(response) => response,
+        // Conformance tests only care about the pong, so carry on with an empty status
+        Err(e) if arguments.fail_on_mismatch_only => {
+            print_warning(
+                format!("The status response has malformed JSON data: {e}").as_ref(),
+                arguments,
+            );
+            Response {
+                description: serde_json::Value::String(String::new()),
+                ..Response::default()
             }
-        };
-        if split.count() != 0 {
-            // We should've read everything in the packet already. If that's not the case this is considered an
-            // invalid format. Skip it.
-            return None;
         }
-
-        Some((motd, port))
-    } else {
-        None
+        Err(e) => {
+            eprintln!("Error: Could not decode response because it has malformed JSON data");
+            eprintln!("More details: {e}");
+            return Err(ErrorCode::MalformedJson);
+        }
+    };
+    if let Some(version) = server_response.version.as_ref().filter(|v| v.coerced) {
+        print_line_verbose(
+            format!(
+                "The server sent an incomplete or malformed version. Using name \'{}\' and protocol {}",
+                version.name, version.protocol
+            )
+            .as_ref(),
+            arguments,
+        );
     }
+
+    Ok(PingResult {
+        status_response_json,
+        server_response,
+        latency: None,
+        pong_payload_matches: true,
+        timing,
+    })
 }
 
-fn print_line_verbose(msg: &str, arguments: &CommandLineArguments) {
-    if arguments.verbose {
-        let _ = stderr().write_all(msg.as_bytes());
-        let _ = stderr().write_all("\n".as_bytes());
+// Some servers misbehave on the ping packet, so we may stop right after the status response. A replayed pong would be
+// meaningless because neither the payload nor the timing are ours.
+fn should_skip_ping(arguments: &CommandLineArguments) -> bool {
+    let skip = arguments.no_ping || arguments.replay.is_some();
+    if skip {
+        print_line_verbose("Skipped ping request", arguments);
     }
+    skip
 }
 
-fn print_warning(msg: &str) {
-    let stderr = std::io::stderr().lock();
-    let print_colors = can_print_colors(&stderr);
-    if print_colors {
-        eprint!("{FG_YELLOW}");
+// Sends the ping request and waits for the pong. Returns the latency and whether the server echoed our payload.
+fn run_ping<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    arguments: &CommandLineArguments,
+    deadline: Option<Instant>,
+) -> Result<(Duration, bool), ErrorCode> {
+    // Calculate server response time. The payload is the current time unless the user wants to see how the server
+    // echoes a specific value.
+    let ping_payload = arguments.ping_payload.unwrap_or_else(|| {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(t) => t.as_secs() as i64,
+            Err(_) => 0,
+        }
+    });
+    let start_time = match send_ping_request(writer, ping_payload) {
+        Ok(time) => time,
+        Err(e) => {
+            eprintln!("Error: Could not send ping request");
+            eprintln!("More details: {e}");
+            return Err(ErrorCode::Protocol);
+        }
+    };
+    print_line_verbose("Sent ping request!", arguments);
+
+    let payload = match read_pong_response(reader, arguments.max_packet_size) {
+        Ok(payload) => payload,
+        Err(e) => {
+            eprintln!("Error: Could not read pong response");
+            eprintln!("More details: {e}");
+            return Err(ErrorCode::Protocol);
+        }
+    };
+
+    let response_elapsed_time = start_time.elapsed();
+    print_line_verbose("Received pong response!", arguments);
+    if deadline_exceeded(deadline) {
+        eprintln!("Error: Deadline exceeded while waiting for the pong response");
+        return Err(ErrorCode::Protocol);
     }
-    eprint!("WARNING: {msg}");
-    if print_colors {
-        eprint!("{RESET_COLORS}");
+    print_line_verbose(
+        format!(
+            "Ping payload sent: 0x{ping_payload:016x}. Pong payload received: 0x{payload:016x}"
+        )
+        .as_ref(),
+        arguments,
+    );
+
+    // Some servers and proxies deliberately echo a different value. Report it, but still print the status we received.
+    let pong_payload_matches = payload == ping_payload;
+    if !pong_payload_matches {
+        print_warning(
+            format!("The server's pong response is an invalid value: 0x{payload:x}. Sent: 0x{ping_payload:x}")
+                .as_ref(),
+            arguments,
+        );
+    }
+
+    print_line_verbose(
+        format!("Delay: {} ms", response_elapsed_time.as_millis()).as_ref(),
+        arguments,
+    );
+
+    Ok((response_elapsed_time, pong_payload_matches))
+}
+
+// Prints the status as soon as it arrives and the latency once the pong comes back, so slow servers feel responsive
+fn ping_with_early_hints<T: Write>(
+    output: &mut T,
+    arguments: &CommandLineArguments,
+    deadline: Option<Instant>,
+    print_colors: bool,
+) -> ErrorCode {
+    let write_failed = |e: io::Error| {
+        eprintln!("Error: Could not write output");
+        eprintln!("More details: {e}");
+        ErrorCode::IoError
+    };
+    let result = connect_and_run(arguments, deadline, |mut reader, mut writer| {
+        let status = request_status(&mut reader, &mut writer, arguments, deadline)?;
+        let exit_code = check_status(&status.server_response, arguments);
+        print_ping_result(output, status, arguments, print_colors)
+            .and_then(|_| output.flush())
+            .map_err(write_failed)?;
+        if should_skip_ping(arguments) {
+            return Ok(exit_code);
+        }
+
+        let (latency, pong_payload_matches) =
+            run_ping(&mut reader, &mut writer, arguments, deadline)?;
+        let mut rows = [("Server latency", format_latency(Some(latency)))];
+        if arguments.describe {
+            describe_rows(&mut rows);
+        }
+        write_table(output, &rows).map_err(write_failed)?;
+        Ok(exit_code_after_ping(
+            pong_payload_matches,
+            exit_code,
+            arguments,
+        ))
+    });
+    print_line_verbose("Disconnected", arguments);
+
+    match result {
+        Ok(exit_code) => exit_code,
+        Err(error_code) => error_code,
     }
-    eprintln!();
 }
 
-fn can_print_colors<T: IsTerminal>(stream_handle: &T) -> bool {
-    // Determines whether we should show ANSI colors and other font styles or not. Based on http://bixense.com/clicolors/
-    let no_color_set = std::env::var("NO_COLOR").map_or(false, |v| v == "1");
-    if no_color_set {
-        return false;
+fn run_login_probe<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    arguments: &CommandLineArguments,
+    deadline: Option<Instant>,
+) -> Result<LoginResponse, ErrorCode> {
+    start_handshake(writer, arguments, HANDSHAKE_LOGIN)?;
+
+    if let Err(e) = send_login_start(writer, &arguments.username, arguments.protocol) {
+        eprintln!("Error: Could not send login start");
+        eprintln!("More details: {e}");
+        return Err(ErrorCode::Protocol);
+    }
+    print_line_verbose("Login start sent!", arguments);
+    if deadline_exceeded(deadline) {
+        eprintln!("Error: Deadline exceeded while sending the login start");
+        return Err(ErrorCode::Protocol);
+    }
+
+    let login_response = match read_login_response(reader, arguments.max_packet_size) {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("Error: Could not read login response");
+            eprintln!("More details: {e}");
+            return Err(ErrorCode::Protocol);
+        }
+    };
+    print_line_verbose("Received login response!", arguments);
+    if deadline_exceeded(deadline) {
+        eprintln!("Error: Deadline exceeded while reading the login response");
+        return Err(ErrorCode::Protocol);
+    }
+
+    if let Some(brand) = &arguments.brand {
+        if login_response != LoginResponse::LoginSuccess {
+            print_line_verbose(
+                "Didn't send the client brand because the server didn't let us in",
+                arguments,
+            );
+        } else if arguments.protocol < FIRST_CONFIGURATION_PROTOCOL {
+            // Older releases expect the brand in the play state, where packet IDs change with every release
+            print_line_verbose(
+                "Didn't send the client brand because it's only supported for 1.20.2 or newer",
+                arguments,
+            );
+        } else {
+            if let Err(e) = send_client_brand(writer, brand, arguments.protocol) {
+                eprintln!("Error: Could not send client brand");
+                eprintln!("More details: {e}");
+                return Err(ErrorCode::Protocol);
+            }
+            print_line_verbose("Client brand sent!", arguments);
+        }
     }
 
-    let clicolor_force_set = std::env::var("CLICOLOR_FORCE").map_or(false, |v| v == "1");
-    if clicolor_force_set {
-        return true;
+    Ok(login_response)
+}
+
+fn start_handshake<W: Write>(
+    writer: &mut W,
+    arguments: &CommandLineArguments,
+    next_state: i32,
+) -> Result<(), ErrorCode> {
+    // We need to ensure that we send the hostname (if provided) instead of the IP address because otherwise some servers
+    // may not respond at all. Proxies route by this address, so the user may advertise a different one than we dial.
+    let mut server_address = arguments
+        .handshake_host
+        .as_ref()
+        .unwrap_or(&arguments.host)
+        .clone();
+    if let Some(forge) = arguments.forge {
+        server_address.push_str(forge.handshake_suffix());
     }
+    match send_handshake(
+        writer,
+        &server_address,
+        arguments.port,
+        arguments.protocol,
+        next_state,
+    ) {
+        Ok(response) => response,
+        Err(e) if is_connection_closed_error(&e) => {
+            eprintln!(
+                "Error: The server closed the connection (possibly rate-limited or IP-banned)"
+            );
+            eprintln!("More details: {e}");
+            return Err(ErrorCode::Protocol);
+        }
+        Err(e) => {
+            eprintln!("Error: Could not send handshake");
+            eprintln!("More details: {e}");
+            return Err(ErrorCode::Protocol);
+        }
+    };
+    print_line_verbose("Handshake request sent!", arguments);
+    Ok(())
+}
+
+fn send_handshake<T: Write>(
+    output: &mut T,
+    server_address: &str,
+    port: u16,
+    protocol: i32,
+    next_state: i32,
+) -> Result<(), io::Error> {
+    let mut buffer: Vec<u8> = Vec::with_capacity(4096);
+    let invalid_data = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+
+    // Protocol version
+    write_var_int(&mut buffer, protocol).map_err(invalid_data)?;
+
+    // Server address
+    write_string(&mut buffer, server_address).map_err(invalid_data)?;
 
-    stream_handle.is_terminal()
+    // Server port
+    write_unsigned_short(&mut buffer, port).map_err(invalid_data)?;
+
+    // Next state
+    write_var_int(&mut buffer, next_state).map_err(invalid_data)?;
+
+    // The packet is built in memory first so that I/O errors keep their kind. Servers often drop the connection right
+    // away, and we want to tell that apart from other failures.
+    let mut packet: Vec<u8> = Vec::with_capacity(buffer.len() + 5);
+    write_packet(&mut packet, 0, &buffer).map_err(invalid_data)?;
+    output.write_all(&packet)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn is_connection_closed_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+fn send_status_request<T: Write>(output: &mut T) -> Result<(), String> {
+    // The status request has no fields
+    write_packet(output, 0, &[])?;
+    output.flush().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn send_ping_request<T: Write>(output: &mut T, payload: i64) -> Result<Instant, String> {
+    let mut buffer: Vec<u8> = Vec::with_capacity(8);
+
+    // Payload
+    write_long(&mut buffer, payload)?;
+
+    write_packet(output, 1, &buffer)?;
+    output.flush().map_err(|e| e.to_string())?;
+
+    Ok(Instant::now())
+}
+
+fn send_login_start<T: Write>(output: &mut T, username: &str, protocol: i32) -> Result<(), String> {
+    let mut buffer: Vec<u8> = Vec::with_capacity(64);
+
+    // Player name
+    write_string(&mut buffer, username)?;
+
+    // The rest of the packet changed several times during 1.19. We never send a signature, and the player UUID is left
+    // as zeros because offline servers derive it from the name and online servers get it from the session server.
+    match protocol {
+        // Has signature data
+        759 => buffer.push(0),
+        // Has signature data and has player UUID
+        760 => buffer.extend_from_slice(&[0, 0]),
+        // Has player UUID
+        761..=763 => buffer.push(0),
+        // Player UUID
+        764.. => buffer.extend_from_slice(&[0; 16]),
+        _ => {}
+    }
+
+    write_packet(output, 0, &buffer)?;
+    output.flush().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn send_client_brand<T: Write>(output: &mut T, brand: &str, protocol: i32) -> Result<(), String> {
+    // Acknowledge the login success to move to the configuration state. This packet has no fields.
+    write_packet(output, 3, &[])?;
+
+    // Plugin message on the "minecraft:brand" channel. Its data is the brand as a string.
+    let mut buffer: Vec<u8> = Vec::with_capacity(brand.len() + 32);
+    write_string(&mut buffer, "minecraft:brand")?;
+    write_string(&mut buffer, brand)?;
+
+    // 1.20.5 added a packet before the plugin message in the configuration state
+    let packet_id = if protocol >= 766 { 2 } else { 1 };
+    write_packet(output, packet_id, &buffer)?;
+    output.flush().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn read_login_response<T: Read>(
+    input: &mut T,
+    max_packet_size: usize,
+) -> Result<LoginResponse, String> {
+    let (packet_id, body) = read_packet(input, max_packet_size)?;
+    match packet_id {
+        0 => {
+            // Reason
+            let mut body = body.as_slice();
+            let reason = read_string(&mut body)?;
+
+            // Check if all bytes were read successfully
+            if !body.is_empty() {
+                return Err(format!(
+                    "ERROR: could not deserialize packet. There are {} bytes left after the disconnect reason.",
+                    body.len()
+                ));
+            }
+
+            Ok(LoginResponse::Disconnect(reason))
+        }
+        1 => Ok(LoginResponse::EncryptionRequest),
+        2 => Ok(LoginResponse::LoginSuccess),
+        3 => Ok(LoginResponse::SetCompression),
+        4 => Ok(LoginResponse::PluginRequest),
+        _ => Err(format!(
+            "Error: The server responded with an unknown packet ID: 0x{packet_id:x}"
+        )),
+    }
+}
+
+fn read_status_response<T: Read>(
+    input: &mut T,
+    max_packet_size: usize,
+    lossy: bool,
+) -> Result<String, String> {
+    let first_byte = [read_first_byte(input)?];
+    // The packet length is the first VarInt, and a single 0 byte encodes a length of 0: not even a packet ID follows
+    if first_byte[0] == 0 {
+        return Err("The server sent an empty status packet".to_owned());
+    }
+    let mut input = first_byte.as_slice().chain(input);
+    let (packet_id, body) = read_packet(&mut input, max_packet_size)?;
+    if let Some(packet_name) = login_packet_name(packet_id) {
+        return Err(format!(
+            "Error: The server responded with an {packet_name} (packet ID 0x{packet_id:x}). It may be misconfigured to treat status requests as logins"
+        ));
+    }
+    if packet_id != 0 {
+        return Err(format!(
+            "Error: The server responded with an unknown packet ID: 0x{packet_id:x}"
+        ));
+    }
+    if body.is_empty() {
+        return Err("The server sent an empty status packet".to_owned());
+    }
+
+    // JSON response
+    let mut body = body.as_slice();
+    let server_info = if lossy {
+        read_string_lossy(&mut body)?
+    } else {
+        read_string(&mut body)?
+    };
+
+    // Check if all bytes were read successfully
+    if !body.is_empty() {
+        return Err(format!(
+            "ERROR: could not deserialize packet. There are {} bytes left after the status response.",
+            body.len()
+        ));
+    }
+
+    Ok(server_info)
+}
+
+// Servers older than 1.7 don't understand the modern handshake and answer with a kick packet from the legacy protocol
+#[derive(PartialEq, Debug)]
+enum ResponseProtocol {
+    Modern,
+    Legacy,
+}
+
+// There's no --auto mode that falls back to the legacy ping yet
+#[allow(dead_code)]
+fn peek_response_protocol<R: BufRead>(reader: &mut R) -> io::Result<ResponseProtocol> {
+    // A legacy kick starts with 0xFF followed by the high byte of its length, which is 0 for any message short enough
+    // for the server list. A modern packet may also start with 0xFF when its length takes more than one byte, but then
+    // the next byte is never 0. Only the buffer is inspected, so the real parser still gets the whole packet.
+    let buffer = reader.fill_buf()?;
+    match buffer {
+        [] => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the server closed the connection without answering",
+        )),
+        [0xFF, 0x00, ..] => Ok(ResponseProtocol::Legacy),
+        // A lone 0xFF can't be told apart without consuming it. Servers write the start of their answer at once, so
+        // that only happens with a modern packet split right after its first byte.
+        _ => Ok(ResponseProtocol::Modern),
+    }
+}
+
+// Some servers (or firewalls in front of them) accept the handshake and then close the connection without answering.
+// Telling that apart from a malformed response needs the error kind, so the first byte is read on its own.
+fn read_first_byte<T: Read>(input: &mut T) -> Result<u8, String> {
+    let mut byte = [0; 1];
+    match input.read_exact(&mut byte) {
+        Ok(()) => Ok(byte[0]),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err("The server accepted the handshake but closed the connection without a status response (possibly due to a protocol mismatch or a firewall)".to_owned()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Packets a server only sends during login. Getting one of them instead of the status response means the server went
+// to the login state even though the handshake asked for the status. 0x00 is left out because it's also the status
+// response.
+fn login_packet_name(packet_id: i32) -> Option<&'static str> {
+    match packet_id {
+        0x01 => Some("Encryption Request"),
+        0x02 => Some("Login Success"),
+        0x03 => Some("Set Compression"),
+        0x04 => Some("Login Plugin Request"),
+        _ => None,
+    }
+}
+
+fn read_pong_response<T: Read>(input: &mut T, max_packet_size: usize) -> Result<i64, String> {
+    let (packet_id, body) = read_packet(input, max_packet_size)?;
+    if packet_id != 1 {
+        return Err(format!(
+            "Error: The server responded with an unknown packet ID: 0x{packet_id:x}"
+        ));
+    }
+
+    // Payload
+    let mut body = body.as_slice();
+    let payload = read_long(&mut body)?;
+
+    // Check if all bytes were read successfully
+    if !body.is_empty() {
+        return Err(format!(
+            "ERROR: could not deserialize packet. There are {} bytes left after the pong payload.",
+            body.len()
+        ));
+    }
+
+    Ok(payload)
+}
+
+fn listen_for_lan_games(arguments: &CommandLineArguments) -> ErrorCode {
+    let (mut output, print_colors) = match open_output(arguments) {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Error: Could not open output file");
+            eprintln!("More details: {e}");
+            return ErrorCode::CannotCreateFile;
+        }
+    };
+
+    // Listen for Open to LAN games. Only Ipv4 sockets are supported.
+    let bind_port = arguments.bind_port.unwrap_or(OPEN_TO_LAN_PORT);
+    let bind_address = SocketAddr::from(([0, 0, 0, 0], bind_port));
+    let ip = bind_address.ip().to_string();
+    let port = bind_address.port().to_string();
+    print_line_verbose(
+        format!("Attempting to bind to {ip}:{port}").as_ref(),
+        arguments,
+    );
+    let socket = match UdpSocket::bind(bind_address) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Error: Could not bind socket to {ip}:{port}");
+            eprintln!("More details: {e}");
+            return ErrorCode::Protocol;
+        }
+    };
+    print_line_verbose("Socket bind successful", arguments);
+
+    let multicast_group = Ipv4Addr::from([224, 0, 2, 60]);
+    // Any interface unless the user picked one, e.g. to skip a VPN adapter
+    let interface = arguments.lan_interface;
+    print_line_verbose(
+        format!("Attempting to join multicast {multicast_group} on interface {interface}").as_ref(),
+        arguments,
+    );
+    if let Err(e) = socket.join_multicast_v4(&multicast_group, &interface) {
+        let multicast_group_ip = multicast_group.to_string();
+        eprintln!("Error: Could not join multicast {multicast_group_ip}");
+        eprintln!("More details: {e}");
+        return ErrorCode::Protocol;
+    }
+    print_line_verbose("Joined multicast grop successfully", arguments);
+
+    // Ctrl-C only raises a flag, so we need to wake up from time to time to see if it's set and leave the multicast
+    // group cleanly
+    if let Err(e) = install_interrupt_handler() {
+        print_warning(
+            format!("Could not handle Ctrl-C, so the multicast group won't be left cleanly: {e}")
+                .as_ref(),
+            arguments,
+        );
+    }
+    if let Err(e) = socket.set_read_timeout(Some(LAN_POLL_INTERVAL)) {
+        eprintln!("Error: Could not set a read timeout on the multicast socket");
+        eprintln!("More details: {e}");
+        return ErrorCode::Protocol;
+    }
+
+    print_line_verbose("Listening for incoming packets...", arguments);
+    let mut unique_lan_servers = HashSet::new();
+    let mut buffer = vec![0; arguments.buffer_size.unwrap_or(LAN_BUFFER_SIZE)];
+    while !INTERRUPTED.load(Ordering::SeqCst) {
+        match socket.recv_from(&mut buffer) {
+            Ok((packet_length, origin_socket)) => {
+                let origin_socket_ip = origin_socket.ip().to_string();
+                let origin_socket_port = origin_socket.port().to_string();
+                if may_be_truncated(packet_length, buffer.len()) {
+                    print_warning(
+                        format!("The packet from {origin_socket_ip}:{origin_socket_port} may have been cut. Use --buffer-size to receive bigger packets")
+                            .as_ref(),
+                        arguments,
+                    );
+                }
+
+                // Parse received data. I refuse to use regular expressions because the format of the message is too simple
+                // to bother adding another dependency.
+                let buffer_portion: Vec<u8> = buffer.iter().cloned().take(packet_length).collect();
+                let message = match String::from_utf8(buffer_portion) {
+                    Ok(s) => s,
+                    Err(e) if arguments.lossy => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                    Err(_) => {
+                        // Invalid format. Skip this packet.
+                        print_line_verbose(format!("Ignored packet from {origin_socket_ip}:{origin_socket_port} because the format is not valid").as_ref(), arguments);
+                        continue;
+                    }
+                };
+
+                if let Some((motd, port)) = parse_open_to_lan_message(&message) {
+                    // Cache known servers as long as we keep listening for LAN games
+                    if unique_lan_servers.insert(message.clone()) {
+                        // Server wasn't cached. Print it only once and ignore further Open to LAN messages from this server
+                        let timestamp = if arguments.timestamps {
+                            format!("{} ", format_timestamp(SystemTime::now()))
+                        } else {
+                            String::new()
+                        };
+                        let result = if arguments.raw_response {
+                            writeln!(output, "{timestamp}{message}")
+                        } else {
+                            let styled_motd = chat::parse_styles_to_string(
+                                motd,
+                                text_format(print_colors),
+                                arguments.obfuscate_mode,
+                            );
+                            writeln!(
+                                output,
+                                "{timestamp}[{origin_socket_ip}:{port}]\t{styled_motd}"
+                            )
+                        };
+                        if let Err(e) = result {
+                            eprintln!("Error: Could not write output");
+                            eprintln!("More details: {e}");
+                            return ErrorCode::IoError;
+                        }
+                        if arguments.once {
+                            print_line_verbose("Found a game. Stopped listening", arguments);
+                            break;
+                        }
+                    } else if arguments.verbose {
+                        print_line_verbose(format!("Ignored packet from {origin_socket_ip}:{origin_socket_port} because this server is already known").as_ref(), arguments);
+                    }
+                } else {
+                    print_line_verbose(format!("Ignored packet from {origin_socket_ip}:{origin_socket_port} because the format is not valid").as_ref(), arguments);
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                // Nothing received yet. Check the interrupt flag and keep listening.
+            }
+            Err(e) => {
+                eprintln!("Error: I/O error when reading incoming data from a multicast socket");
+                eprintln!("More details: {e}");
+                return ErrorCode::Protocol;
+            }
+        }
+    }
+
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        print_line_verbose("Interrupted", arguments);
+    }
+    if let Err(e) = socket.leave_multicast_v4(&multicast_group, &interface) {
+        print_warning(
+            format!(
+                "There was an error when attempting to leave multicast group {multicast_group}"
+            )
+            .as_ref(),
+            arguments,
+        );
+        eprintln!("More details: {e}");
+        return ErrorCode::Protocol;
+    }
+    print_line_verbose(
+        format!("Left multicast group {multicast_group}").as_ref(),
+        arguments,
+    );
+
+    ErrorCode::Ok
+}
+
+// recv_from silently drops whatever doesn't fit in the buffer, so a full buffer is the only hint we get
+fn may_be_truncated(packet_length: usize, buffer_size: usize) -> bool {
+    packet_length >= buffer_size
+}
+
+// Set when the user presses Ctrl-C
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// The only unsafe code in the crate. It calls signal() from the C library directly, which every Unix provides, so we
+// don't need a dependency for a single call.
+#[cfg(unix)]
+fn install_interrupt_handler() -> io::Result<()> {
+    use std::os::raw::c_int;
+
+    // Same definitions as <signal.h>. sighandler_t is a pointer-sized integer that holds either a function pointer or
+    // one of the special values like SIG_ERR.
+    #[allow(non_camel_case_types)]
+    type sighandler_t = usize;
+    const SIG_ERR: sighandler_t = !0;
+    // POSIX fixes SIGINT to 2 on every Unix
+    const SIGINT: c_int = 2;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: sighandler_t) -> sighandler_t;
+    }
+
+    extern "C" fn on_interrupt(_signum: c_int) {
+        // Storing into an atomic is async-signal-safe: it doesn't allocate, lock or call into the C library
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+
+    // SAFETY: signal() is called with a valid signal number and a handler with the C calling convention. The handler
+    // only does async-signal-safe work (see above). It only has to run once, since the first Ctrl-C ends the listener,
+    // so it doesn't matter whether the platform resets the handler after delivering it.
+    let previous_handler =
+        unsafe { signal(SIGINT, on_interrupt as extern "C" fn(c_int) as sighandler_t) };
+    if previous_handler == SIG_ERR {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn install_interrupt_handler() -> io::Result<()> {
+    // Ctrl-C keeps its default behavior on other platforms
+    Ok(())
+}
+
+fn format_timestamp(time: SystemTime) -> String {
+    // ISO 8601 in UTC, e.g. 2024-01-31T18:30:00Z
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let days = (seconds / 86400) as i64;
+    let seconds_of_day = seconds % 86400;
+
+    // Convert days since the epoch to a civil date. Based on http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+fn parse_open_to_lan_message(message: &str) -> Option<(&str, &str)> {
+    if message.starts_with("[MOTD]") && message.ends_with("[/AD]") {
+        // Remove [MOTD] and [/AD] from the message
+        let i_start = "[MOTD]".len();
+        let i_end = message.len() - "[/AD]".len();
+        let trimmed_message = &message[i_start..i_end];
+
+        let mut split = trimmed_message.split("[/MOTD][AD]");
+        let motd = match split.next() {
+            Some(motd) => motd,
+            None => {
+                // Invalid format. Skip this packet.
+                return None;
+            }
+        };
+        let port = match split.next() {
+            Some(port) => port,
+            None => {
+                // Invalid format. Skip this packet.
+                return None;
+            }
+        };
+        if split.count() != 0 {
+            // We should've read everything in the packet already. If that's not the case this is considered an
+            // invalid format. Skip it.
+            return None;
+        }
+
+        Some((motd, port))
+    } else {
+        None
+    }
+}
+
+fn open_output(arguments: &CommandLineArguments) -> io::Result<(Box<dyn Write>, bool)> {
+    // The primary output goes to stdout unless a file was requested. Warnings and verbose messages always go to stderr.
+    match &arguments.output {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(arguments.append)
+                .truncate(!arguments.append)
+                .open(path)?;
+            let print_colors = can_print_colors(&file, arguments);
+            Ok((Box::new(file), print_colors))
+        }
+        None => {
+            let stdout = stdout();
+            let print_colors = can_print_colors(&stdout, arguments);
+            Ok((Box::new(stdout), print_colors))
+        }
+    }
+}
+
+fn deadline_exceeded(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+// Time left until the deadline, or None once it has passed
+fn remaining_time(deadline: Instant) -> Option<Duration> {
+    let remaining_time = deadline.saturating_duration_since(Instant::now());
+    (!remaining_time.is_zero()).then_some(remaining_time)
+}
+
+fn print_line_verbose(msg: &str, arguments: &CommandLineArguments) {
+    if arguments.verbose {
+        // Dim the messages so they stand apart from the actual output and the warnings
+        let mut stderr = stderr().lock();
+        let _ = if can_print_colors(&stderr, arguments) {
+            writeln!(stderr, "{FG_GRAY}{msg}{RESET_COLORS}")
+        } else {
+            writeln!(stderr, "{msg}")
+        };
+    }
+}
+
+fn print_warning(msg: &str, arguments: &CommandLineArguments) {
+    let mut stderr = stderr().lock();
+    let print_colors = can_print_colors(&stderr, arguments);
+    let _ = write_warning(&mut stderr, msg, arguments, print_colors);
+}
+
+fn write_warning<T: Write>(
+    output: &mut T,
+    msg: &str,
+    arguments: &CommandLineArguments,
+    print_colors: bool,
+) -> io::Result<()> {
+    // Errors are always printed, but scripts may not care about warnings
+    if arguments.quiet {
+        return Ok(());
+    }
+    if print_colors {
+        writeln!(output, "{FG_YELLOW}WARNING: {msg}{RESET_COLORS}")
+    } else {
+        writeln!(output, "WARNING: {msg}")
+    }
+}
+
+fn list_colors(arguments: &CommandLineArguments) -> ErrorCode {
+    let (mut output, print_colors) = match open_output(arguments) {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Error: Could not open output file");
+            eprintln!("More details: {e}");
+            return ErrorCode::CannotCreateFile;
+        }
+    };
+
+    let color_depth = detect_color_depth(|name| std::env::var(name).ok());
+    let disabled = if print_colors {
+        ""
+    } else {
+        " (colors are disabled)"
+    };
+    let result = writeln!(output, "Detected color mode: {color_depth}{disabled}")
+        .and_then(|_| write!(output, "{}", chat::color_chart(print_colors)));
+    if let Err(e) = result {
+        eprintln!("Error: Could not write output");
+        eprintln!("More details: {e}");
+        return ErrorCode::IoError;
+    }
+    ErrorCode::Ok
+}
+
+// Best guess of how many colors the terminal supports. There's no reliable way to ask, so this relies on the
+// conventions of COLORTERM and TERM.
+fn detect_color_depth<E: Fn(&str) -> Option<String>>(env: E) -> &'static str {
+    if env("COLORTERM").is_some_and(|v| v == "truecolor" || v == "24bit") {
+        "truecolor"
+    } else if env("TERM").is_some_and(|v| v.contains("256color")) {
+        "256"
+    } else {
+        "16"
+    }
+}
+
+fn can_print_colors<T: IsTerminal>(stream_handle: &T, arguments: &CommandLineArguments) -> bool {
+    // The environment was already read when resolving the policy in main
+    arguments.color_policy.use_colors(stream_handle)
+}
+
+#[cfg(test)]
+mod ping_tests {
+    use super::*;
+    use arguments::ForgeMarker;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const STATUS_JSON: &str = r#"{"version":{"name":"1.20.4","protocol":765},"players":{"max":20,"online":3},"description":"A Minecraft Server"}"#;
+
+    fn read_raw_packet<T: Read>(input: &mut T) -> Vec<u8> {
+        let packet_length = read_var_int(input).unwrap();
+        let mut packet = vec![0; packet_length as usize];
+        input.read_exact(&mut packet).unwrap();
+        packet
+    }
+
+    fn serve_status<S: Read + Write>(mut stream: S) {
+        // Minimal server that speaks the status protocol for a single client
+
+        // Handshake and status request
+        read_raw_packet(&mut stream);
+        assert_eq!(read_raw_packet(&mut stream), [0x00]);
+
+        // Status response
+        let mut body = vec![];
+        write_var_int(&mut body, 0).unwrap();
+        write_string(&mut body, STATUS_JSON).unwrap();
+        write_var_int(&mut stream, body.len() as i32).unwrap();
+        stream.write_all(&body).unwrap();
+
+        // Echo the ping request back as the pong response
+        let ping = read_raw_packet(&mut stream);
+        write_var_int(&mut stream, ping.len() as i32).unwrap();
+        stream.write_all(&ping).unwrap();
+    }
+
+    fn spawn_mock_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_status(stream);
+        });
+        address
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ping_over_unix_socket() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("mping-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_status(stream);
+        });
+
+        let arguments = CommandLineArguments {
+            host: "example.com".to_owned(),
+            unix_socket: Some(path.clone()),
+            ..CommandLineArguments::default()
+        };
+        let result = connect_and_ping(&arguments, None);
+        let _ = std::fs::remove_file(&path);
+
+        let result = result.ok().unwrap();
+        assert_eq!(result.status_response_json, STATUS_JSON);
+        assert!(result.pong_payload_matches);
+    }
+
+    #[test]
+    fn test_ping_closed_port() {
+        // Grab a free port and close it right away so nobody is listening on it
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let arguments = CommandLineArguments {
+            host: address.ip().to_string(),
+            port: address.port(),
+            ..CommandLineArguments::default()
+        };
+        let result = connect_and_ping(&arguments, None);
+        assert!(matches!(result, Err(ErrorCode::Unavailable)));
+    }
+
+    #[test]
+    fn test_ping_with_expired_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let arguments = CommandLineArguments {
+            host: address.ip().to_string(),
+            port: address.port(),
+            ..CommandLineArguments::default()
+        };
+        // Reported as a timeout, not as the server being unavailable
+        let result = connect_and_ping(&arguments, Some(Instant::now()));
+        assert!(matches!(result, Err(ErrorCode::Protocol)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_with_expired_deadline() {
+        use std::os::unix::net::UnixListener;
+
+        let path =
+            std::env::temp_dir().join(format!("mping-test-deadline-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _listener = UnixListener::bind(&path).unwrap();
+        let arguments = CommandLineArguments {
+            host: "example.com".to_owned(),
+            unix_socket: Some(path.clone()),
+            ..CommandLineArguments::default()
+        };
+        let result = connect_and_ping(&arguments, Some(Instant::now()));
+        let _ = std::fs::remove_file(&path);
+        assert!(matches!(result, Err(ErrorCode::Protocol)));
+    }
+
+    #[test]
+    fn test_remaining_time() {
+        assert_eq!(remaining_time(Instant::now()), None);
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert!(remaining_time(deadline).is_some_and(|remaining| remaining > Duration::ZERO));
+    }
+
+    #[test]
+    fn test_handshake_bytes() {
+        let mut packet = vec![];
+        send_handshake(&mut packet, "localhost", 25565, 765, HANDSHAKE_STATUS).unwrap();
+        let expected = [
+            0x10, // Packet length
+            0x00, // Packet ID
+            0xfd, 0x05, // Protocol version 765
+            0x09, b'l', b'o', b'c', b'a', b'l', b'h', b'o', b's', b't', // Server address
+            0x63, 0xdd, // Server port 25565
+            0x01, // Next state
+        ];
+        assert_eq!(packet, expected);
+    }
+
+    #[test]
+    fn test_handshake_host_override() {
+        let arguments = CommandLineArguments {
+            host: "127.0.0.1".to_owned(),
+            handshake_host: Some("a\0FML\0".to_owned()),
+            protocol: 765,
+            ..CommandLineArguments::default()
+        };
+        let mut packet = vec![];
+        start_handshake(&mut packet, &arguments, HANDSHAKE_STATUS).unwrap();
+        let expected = [
+            0x0d, // Packet length
+            0x00, // Packet ID
+            0xfd, 0x05, // Protocol version 765
+            0x06, b'a', 0x00, b'F', b'M', b'L', 0x00, // Server address
+            0x63, 0xdd, // Server port 25565
+            0x01, // Next state
+        ];
+        assert_eq!(packet, expected);
+    }
+
+    #[test]
+    fn test_handshake_forge_suffix() {
+        let arguments = CommandLineArguments {
+            host: "a".to_owned(),
+            forge: Some(ForgeMarker::Fml2),
+            protocol: 765,
+            ..CommandLineArguments::default()
+        };
+        let mut packet = vec![];
+        start_handshake(&mut packet, &arguments, HANDSHAKE_STATUS).unwrap();
+        let expected = [
+            0x0e, // Packet length
+            0x00, // Packet ID
+            0xfd, 0x05, // Protocol version 765
+            0x07, b'a', 0x00, b'F', b'M', b'L', b'2', 0x00, // Server address
+            0x63, 0xdd, // Server port 25565
+            0x01, // Next state
+        ];
+        assert_eq!(packet, expected);
+    }
+
+    #[test]
+    fn test_status_request_bytes() {
+        let mut packet = vec![];
+        send_status_request(&mut packet).unwrap();
+        assert_eq!(packet, [0x01, 0x00]);
+    }
+
+    #[test]
+    fn test_ping_request_bytes() {
+        let mut packet = vec![];
+        send_ping_request(&mut packet, 0x0102030405060708).unwrap();
+        let expected = [
+            0x09, // Packet length
+            0x01, // Packet ID
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, // Payload
+        ];
+        assert_eq!(packet, expected);
+    }
+
+    struct BrokenPipeWriter;
+
+    impl Write for BrokenPipeWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::from(io::ErrorKind::BrokenPipe))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::from(io::ErrorKind::BrokenPipe))
+        }
+    }
+
+    #[test]
+    fn test_handshake_on_closed_connection() {
+        let mut writer = BufWriter::new(BrokenPipeWriter);
+        let error =
+            send_handshake(&mut writer, "localhost", 25565, 765, HANDSHAKE_STATUS).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        assert!(is_connection_closed_error(&error));
+    }
+
+    // Behaves like a connection that stays open after the server sent everything it had
+    struct OpenConnection<'a> {
+        data: &'a [u8],
+    }
+
+    impl Read for OpenConnection<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            assert!(
+                !self.data.is_empty(),
+                "read past the end of the packet, this would block forever"
+            );
+            self.data.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_read_status_response_does_not_read_past_the_packet() {
+        let mut body = vec![];
+        write_string(&mut body, STATUS_JSON).unwrap();
+        let mut packet = vec![];
+        write_packet(&mut packet, 0, &body).unwrap();
+
+        let mut connection = OpenConnection { data: &packet };
+        assert_eq!(
+            read_status_response(&mut connection, DEFAULT_MAX_PACKET_SIZE, false),
+            Ok(STATUS_JSON.to_owned())
+        );
+    }
+
+    fn write_raw_packet<T: Write>(output: &mut T, packet_id: i32, body: &[u8]) {
+        let mut packet = vec![];
+        write_var_int(&mut packet, packet_id).unwrap();
+        packet.extend_from_slice(body);
+        write_var_int(output, packet.len() as i32).unwrap();
+        output.write_all(&packet).unwrap();
+    }
+
+    #[test]
+    fn test_early_hints_prints_latency_after_the_table() {
+        let address = spawn_mock_server();
+        let arguments = CommandLineArguments {
+            host: address.ip().to_string(),
+            port: address.port(),
+            early_hints: true,
+            ..CommandLineArguments::default()
+        };
+        let mut output = vec![];
+        let exit_code = ping_with_early_hints(&mut output, &arguments, None, false);
+        assert_eq!(exit_code, ErrorCode::Ok);
+
+        let output = String::from_utf8(output).unwrap();
+        let last_line = output.lines().next_back().unwrap();
+        assert!(last_line.starts_with("Server latency"), "{output}");
+        assert_eq!(output.matches("Server latency").count(), 1);
+    }
+
+    #[test]
+    fn test_protocol_fallback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            // Picky server: drop clients that claim protocol 0 right after the handshake
+            let (mut stream, _) = listener.accept().unwrap();
+            let handshake = read_raw_packet(&mut stream);
+            assert_eq!(handshake[1], 0);
+            drop(stream);
+
+            let (stream, _) = listener.accept().unwrap();
+            serve_status(stream);
+        });
+
+        let arguments = CommandLineArguments {
+            host: address.ip().to_string(),
+            port: address.port(),
+            protocol_fallback: true,
+            ..CommandLineArguments::default()
+        };
+        let result = connect_and_ping_with_fallback(&arguments, None);
+        assert_eq!(result.ok().unwrap().status_response_json, STATUS_JSON);
+    }
+
+    #[test]
+    fn test_login_probe_against_whitelisted_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            // The handshake must ask for the login state
+            let handshake = read_raw_packet(&mut stream);
+            assert_eq!(handshake.last(), Some(&(HANDSHAKE_LOGIN as u8)));
+
+            // Login start carries the player name
+            let login_start = read_raw_packet(&mut stream);
+            let mut body = &login_start[1..];
+            assert_eq!(read_string(&mut body), Ok("Notch".to_owned()));
+
+            let mut reason = vec![];
+            write_string(
+                &mut reason,
+                r#"{"text":"You are not white-listed on this server!"}"#,
+            )
+            .unwrap();
+            write_raw_packet(&mut stream, 0, &reason);
+        });
+
+        let arguments = CommandLineArguments {
+            host: "localhost".to_owned(),
+            port: address.port(),
+            login_probe: true,
+            username: "Notch".to_owned(),
+            protocol: 765,
+            ..CommandLineArguments::default()
+        };
+        let connection = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(&connection);
+        let mut writer = BufWriter::new(&connection);
+        let response = run_login_probe(&mut reader, &mut writer, &arguments, None);
+        assert_eq!(
+            response.ok(),
+            Some(LoginResponse::Disconnect(
+                r#"{"text":"You are not white-listed on this server!"}"#.to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_read_login_response_encryption_request() {
+        let mut packet = vec![];
+        write_raw_packet(&mut packet, 1, &[0, 0, 0]);
+        assert_eq!(
+            read_login_response(&mut packet.as_slice(), DEFAULT_MAX_PACKET_SIZE),
+            Ok(LoginResponse::EncryptionRequest)
+        );
+    }
+
+    #[test]
+    fn test_capture_status_and_pong() {
+        let mut body = vec![];
+        write_string(&mut body, STATUS_JSON).unwrap();
+        let mut response = vec![];
+        write_packet(&mut response, 0, &body).unwrap();
+        write_packet(&mut response, 1, &0i64.to_be_bytes()).unwrap();
+        let packets_length = response.len();
+        // Whatever comes after the pong isn't part of the exchange
+        response.extend_from_slice(&[0xde, 0xad]);
+
+        let arguments = CommandLineArguments {
+            host: "localhost".to_owned(),
+            ..CommandLineArguments::default()
+        };
+        let mut capture = vec![];
+        let mut reader = TeeReader::new(response.as_slice(), &mut capture);
+        let result = run_status_ping(&mut reader, &mut vec![], &arguments, None);
+        assert!(result.is_ok());
+        assert_eq!(capture, response[..packets_length]);
+    }
+
+    #[test]
+    fn test_replay_status_response() {
+        let mut body = vec![];
+        write_string(&mut body, STATUS_JSON).unwrap();
+        let mut response = vec![];
+        write_packet(&mut response, 0, &body).unwrap();
+        write_packet(&mut response, 1, &0i64.to_be_bytes()).unwrap();
+        let path = std::env::temp_dir().join(format!("mping-test-{}.bin", std::process::id()));
+        std::fs::write(&path, &response).unwrap();
+
+        let arguments = CommandLineArguments {
+            replay: Some(path.clone()),
+            ..CommandLineArguments::default()
+        };
+        let result = connect_and_ping(&arguments, None);
+        let _ = std::fs::remove_file(&path);
+
+        let result = result.ok().unwrap();
+        assert_eq!(result.status_response_json, STATUS_JSON);
+        assert_eq!(result.latency, None);
+        assert!(result.pong_payload_matches);
+    }
+
+    #[test]
+    fn test_replay_missing_file() {
+        let arguments = CommandLineArguments {
+            replay: Some(std::path::PathBuf::from("/nonexistent/mping-replay.bin")),
+            ..CommandLineArguments::default()
+        };
+        let result = connect_and_ping(&arguments, None);
+        assert!(matches!(result, Err(ErrorCode::CannotOpenInput)));
+    }
+
+    #[test]
+    fn test_read_status_response_encryption_request() {
+        let mut response = vec![];
+        write_packet(&mut response, 1, &[0, 0]).unwrap();
+        let error = read_status_response(&mut response.as_slice(), DEFAULT_MAX_PACKET_SIZE, false)
+            .unwrap_err();
+        assert!(error.contains("Encryption Request"), "{error}");
+        assert!(error.contains("misconfigured"), "{error}");
+    }
+
+    #[test]
+    fn test_read_status_response_zero_length_packet() {
+        let response: &[u8] = &[0x00];
+        let error =
+            read_status_response(&mut &response[..], DEFAULT_MAX_PACKET_SIZE, false).unwrap_err();
+        assert_eq!(error, "The server sent an empty status packet");
+    }
+
+    #[test]
+    fn test_read_status_response_without_body() {
+        let mut response = vec![];
+        write_packet(&mut response, 0, &[]).unwrap();
+        let error = read_status_response(&mut response.as_slice(), DEFAULT_MAX_PACKET_SIZE, false)
+            .unwrap_err();
+        assert_eq!(error, "The server sent an empty status packet");
+    }
+
+    #[test]
+    fn test_read_status_response_unknown_packet() {
+        let mut response = vec![];
+        write_packet(&mut response, 0x7f, &[]).unwrap();
+        let error = read_status_response(&mut response.as_slice(), DEFAULT_MAX_PACKET_SIZE, false)
+            .unwrap_err();
+        assert!(error.contains("unknown packet ID: 0x7f"), "{error}");
+    }
+
+    #[test]
+    fn test_connection_closed_before_status_response() {
+        let error =
+            read_status_response(&mut io::empty(), DEFAULT_MAX_PACKET_SIZE, false).unwrap_err();
+        assert!(
+            error.contains("closed the connection without a status response"),
+            "{error}"
+        );
+
+        let arguments = CommandLineArguments {
+            host: "localhost".to_owned(),
+            ..CommandLineArguments::default()
+        };
+        let result = run_status_ping(&mut io::empty(), &mut vec![], &arguments, None);
+        assert!(matches!(result, Err(ErrorCode::Protocol)));
+    }
+
+    #[test]
+    fn test_ping_with_malformed_json() {
+        let mut body = vec![];
+        write_string(&mut body, r#"{"description": "#).unwrap();
+        let mut response = vec![];
+        write_packet(&mut response, 0, &body).unwrap();
+
+        let arguments = CommandLineArguments {
+            host: "localhost".to_owned(),
+            ..CommandLineArguments::default()
+        };
+        let result = run_status_ping(&mut response.as_slice(), &mut vec![], &arguments, None);
+        assert!(matches!(result, Err(ErrorCode::MalformedJson)));
+    }
+
+    #[test]
+    fn test_ping_with_malformed_json_when_only_the_pong_matters() {
+        let mut body = vec![];
+        write_string(&mut body, r#"{"description": "#).unwrap();
+        let mut response = vec![];
+        write_packet(&mut response, 0, &body).unwrap();
+        write_packet(&mut response, 1, &0i64.to_be_bytes()).unwrap();
+
+        let arguments = CommandLineArguments {
+            host: "localhost".to_owned(),
+            ping_payload: Some(0),
+            fail_on_mismatch_only: true,
+            quiet: true,
+            ..CommandLineArguments::default()
+        };
+        let result = run_status_ping(&mut response.as_slice(), &mut vec![], &arguments, None);
+        assert!(result.unwrap().pong_payload_matches);
+    }
+
+    #[test]
+    fn test_send_client_brand() {
+        let mut packets = vec![];
+        send_client_brand(&mut packets, "vanilla", 765).unwrap();
+
+        let mut packets = packets.as_slice();
+        assert_eq!(
+            read_packet(&mut packets, DEFAULT_MAX_PACKET_SIZE),
+            Ok((3, vec![]))
+        );
+        let (packet_id, body) = read_packet(&mut packets, DEFAULT_MAX_PACKET_SIZE).unwrap();
+        assert_eq!(packet_id, 1);
+        let mut body = body.as_slice();
+        assert_eq!(read_string(&mut body), Ok("minecraft:brand".to_owned()));
+        assert_eq!(read_string(&mut body), Ok("vanilla".to_owned()));
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn test_send_login_start_before_1_19() {
+        let mut packet = vec![];
+        send_login_start(&mut packet, "Notch", 47).unwrap();
+        assert_eq!(packet, [0x07, 0x00, 0x05, b'N', b'o', b't', b'c', b'h']);
+    }
+
+    #[test]
+    fn test_send_login_start_includes_uuid() {
+        let mut packet = vec![];
+        send_login_start(&mut packet, "Notch", 765).unwrap();
+        assert_eq!(packet.len(), 1 + 7 + 16);
+        assert_eq!(&packet[8..], [0; 16]);
+    }
+}
+
+#[cfg(test)]
+mod template_tests {
+    use super::*;
+
+    fn value(name: &str) -> Option<String> {
+        match name {
+            "version" => Some("1.20.4".to_owned()),
+            "online" => Some("3".to_owned()),
+            "max" => Some("20".to_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand_template_without_placeholders() {
+        assert_eq!(
+            expand_template("no placeholders", value),
+            Ok("no placeholders".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_expand_template_placeholders() {
+        let expected = Ok("1.20.4 3/20".to_owned());
+        assert_eq!(expand_template("{version} {online}/{max}", value), expected);
+    }
+
+    #[test]
+    fn test_expand_template_escaped_braces() {
+        let expected = Ok("{version} is 1.20.4".to_owned());
+        assert_eq!(expand_template("{{version}} is {version}", value), expected);
+    }
+
+    #[test]
+    fn test_expand_template_unknown_placeholder() {
+        let result = expand_template("{version} {motto}", value);
+        assert!(result.unwrap_err().contains("{motto}"));
+    }
+
+    #[test]
+    fn test_expand_template_unterminated_placeholder() {
+        assert!(expand_template("{version", value).is_err());
+    }
+}
+
+#[cfg(test)]
+mod truncate_text_tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_short_text() {
+        assert_eq!(
+            truncate_text("A Minecraft Server", 40),
+            "A Minecraft Server"
+        );
+    }
+
+    #[test]
+    fn test_truncate_long_text() {
+        assert_eq!(truncate_text("A Minecraft Server", 10), "A Minec...");
+    }
+
+    #[test]
+    fn test_truncate_multibyte_text() {
+        assert_eq!(truncate_text("ñññññ", 4), "ñ...");
+    }
+
+    #[test]
+    fn test_truncate_to_tiny_width() {
+        assert_eq!(truncate_text("A Minecraft Server", 2), "A ");
+    }
+}
+
+#[cfg(test)]
+mod motd_tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_two_line_motd() {
+        let description =
+            json!({"text": "A Minecraft Server", "extra": ["\n", {"text": "Second line"}]});
+        let motd = chat::parse_chat_object_json_to_string(
+            &description,
+            chat::TextFormat::Plain,
+            chat::ObfuscateMode::Blink,
+        );
+        assert_eq!(clamp_lines(&motd, None), "A Minecraft Server\nSecond line");
+    }
+
+    #[test]
+    fn test_clean_motd_collapses_whitespace() {
+        assert_eq!(
+            clean_motd("      Welcome   to\n\t  the server!   "),
+            "Welcome to the server!"
+        );
+    }
+
+    #[test]
+    fn test_clean_motd_removes_control_characters() {
+        assert_eq!(clean_motd("A\u{7}B\u{0}C\r\nD\u{1b}"), "ABC D");
+    }
+
+    #[test]
+    fn test_single_line_motd_without_cleaning() {
+        assert_eq!(single_line_motd("  First\nSecond", false), "  First Second");
+    }
+
+    #[test]
+    fn test_clamp_motd_lines() {
+        assert_eq!(
+            clamp_lines("First line\nSecond line", Some(1)),
+            "First line"
+        );
+    }
+
+    #[test]
+    fn test_clamp_motd_with_windows_line_endings() {
+        assert_eq!(
+            clamp_lines("First line\r\nSecond line", Some(2)),
+            "First line\nSecond line"
+        );
+    }
+}
+
+#[cfg(test)]
+mod pick_address_tests {
+    use super::*;
+
+    fn addresses() -> Vec<SocketAddr> {
+        [
+            "[2001:db8::1]:25565",
+            "192.0.2.1:25565",
+            "[2001:db8::2]:25565",
+            "192.0.2.2:25565",
+        ]
+        .iter()
+        .map(|address| address.parse().unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn test_pick_any_address() {
+        let picked = pick_address(addresses().into_iter(), AddressFamily::Any);
+        assert_eq!(picked, Some("[2001:db8::1]:25565".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_pick_ipv4_address() {
+        let picked = pick_address(addresses().into_iter(), AddressFamily::Ipv4);
+        assert_eq!(picked, Some("192.0.2.1:25565".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_pick_ipv6_address() {
+        let picked = pick_address(addresses().into_iter(), AddressFamily::Ipv6);
+        assert_eq!(picked, Some("[2001:db8::1]:25565".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_pick_missing_family() {
+        let ipv4_only = addresses().into_iter().filter(SocketAddr::is_ipv4);
+        assert_eq!(pick_address(ipv4_only, AddressFamily::Ipv6), None);
+        assert_eq!(pick_address(std::iter::empty(), AddressFamily::Any), None);
+    }
+}
+
+#[cfg(test)]
+mod write_table_tests {
+    use super::*;
+
+    #[test]
+    fn test_write_table_short_labels() {
+        let mut output = vec![];
+        let rows = [
+            ("Players", "3/20".to_owned()),
+            ("Protocol", "765".to_owned()),
+        ];
+        write_table(&mut output, &rows).unwrap();
+        let expected = format!("{:<24} 3/20\n{:<24} 765\n", "Players", "Protocol");
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_every_table_field_is_described() {
+        let labels = [
+            "Server version",
+            "Protocol",
+            "Players",
+            "Favicon",
+            "Enforces secure chat",
+            "Previews chat",
+            "Server latency",
+        ];
+        for label in labels {
+            assert!(field_description(label).is_some(), "{label}");
+        }
+        assert_eq!(field_description("Unknown field"), None);
+    }
+
+    #[test]
+    fn test_write_extra_fields() {
+        let mut extra_fields = HashMap::new();
+        extra_fields.insert("preventsChatReports".to_owned(), serde_json::json!(true));
+        extra_fields.insert("modinfo".to_owned(), serde_json::json!({"type": "FML"}));
+        let mut output = vec![];
+        write_extra_fields(&mut output, &extra_fields).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Extra fields:\n  modinfo: {\"type\":\"FML\"}\n  preventsChatReports: true\n"
+        );
+
+        let mut output = vec![];
+        write_extra_fields(&mut output, &HashMap::new()).unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_timing_rows() {
+        let timing = Timing {
+            resolve: Some(Duration::from_micros(1500)),
+            connect: Some(Duration::from_millis(20)),
+            status: Some(Duration::from_micros(42_300)),
+        };
+        let rows = timing_rows(&timing, Some(Duration::from_millis(19)));
+        let mut output = vec![];
+        write_table(&mut output, &rows).unwrap();
+        let expected = format!(
+            "{:<24} 1.5 ms\n{:<24} 20.0 ms\n{:<24} 42.3 ms\n{:<24} 19.0 ms\n",
+            "  DNS resolution", "  TCP connection", "  Status response", "  Ping round trip"
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_timing_rows_without_connection() {
+        let rows = timing_rows(&Timing::default(), None);
+        assert!(rows.iter().all(|(_, value)| value == "unavailable"));
+    }
+
+    #[test]
+    fn test_write_table_long_label() {
+        let mut output = vec![];
+        let long_label = "A label that is longer than usual";
+        let rows = [
+            ("Players", "3/20".to_owned()),
+            (long_label, "Yes".to_owned()),
+        ];
+        write_table(&mut output, &rows).unwrap();
+        let expected = format!("{:<33} 3/20\n{long_label} Yes\n", "Players");
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+}
+
+#[cfg(test)]
+mod unique_in_order_tests {
+    use super::*;
+
+    #[test]
+    fn test_unique_in_order() {
+        let items = ["b", "a", "b", "c", "a"].map(String::from).to_vec();
+        assert_eq!(unique_in_order(items), ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn test_unique_in_order_empty() {
+        assert!(unique_in_order(vec![]).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod expected_version_tests {
+    use super::*;
+
+    fn version(name: &str, protocol: i32) -> ResponseVersion {
+        ResponseVersion {
+            name: name.to_owned(),
+            protocol,
+            coerced: false,
+        }
+    }
+
+    #[test]
+    fn test_no_expectations() {
+        let arguments = CommandLineArguments::default();
+        assert_eq!(check_expected_version(None, &arguments), ErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_expect_version_substring() {
+        let arguments = CommandLineArguments {
+            expect_version: Some("1.20.4".to_owned()),
+            ..CommandLineArguments::default()
+        };
+        let matching = version("Paper 1.20.4", 765);
+        let mismatching = version("Paper 1.20.2", 764);
+        assert_eq!(
+            check_expected_version(Some(&matching), &arguments),
+            ErrorCode::Ok
+        );
+        assert_eq!(
+            check_expected_version(Some(&mismatching), &arguments),
+            ErrorCode::UnexpectedVersion
+        );
+    }
+
+    #[test]
+    fn test_expect_protocol() {
+        let arguments = CommandLineArguments {
+            expect_protocol: Some(765),
+            ..CommandLineArguments::default()
+        };
+        assert_eq!(
+            check_expected_version(Some(&version("1.20.3", 765)), &arguments),
+            ErrorCode::Ok
+        );
+        assert_eq!(
+            check_expected_version(Some(&version("1.20.4", 764)), &arguments),
+            ErrorCode::UnexpectedVersion
+        );
+    }
+
+    #[test]
+    fn test_expect_version_and_protocol() {
+        let arguments = CommandLineArguments {
+            expect_version: Some("1.20.4".to_owned()),
+            expect_protocol: Some(765),
+            ..CommandLineArguments::default()
+        };
+        assert_eq!(
+            check_expected_version(Some(&version("1.20.4", 765)), &arguments),
+            ErrorCode::Ok
+        );
+        assert_eq!(
+            check_expected_version(Some(&version("1.20.4", 764)), &arguments),
+            ErrorCode::UnexpectedVersion
+        );
+    }
+
+    #[test]
+    fn test_expectation_without_reported_version() {
+        let arguments = CommandLineArguments {
+            expect_protocol: Some(765),
+            ..CommandLineArguments::default()
+        };
+        assert_eq!(
+            check_expected_version(None, &arguments),
+            ErrorCode::UnexpectedVersion
+        );
+    }
+}
+
+#[cfg(test)]
+mod player_count_tests {
+    use super::*;
+
+    fn players(online: i32, max: i32) -> ResponsePlayers {
+        ResponsePlayers { online, max }
+    }
+
+    #[test]
+    fn test_no_checks() {
+        let arguments = CommandLineArguments::default();
+        assert_eq!(
+            check_player_count(Some(&players(0, 20)), &arguments),
+            ErrorCode::Ok
+        );
+        assert_eq!(
+            check_player_count(Some(&players(20, 20)), &arguments),
+            ErrorCode::Ok
+        );
+    }
+
+    #[test]
+    fn test_fail_on_empty() {
+        let arguments = CommandLineArguments {
+            fail_on_empty: true,
+            ..CommandLineArguments::default()
+        };
+        assert_eq!(
+            check_player_count(Some(&players(0, 20)), &arguments),
+            ErrorCode::PlayerCount
+        );
+        assert_eq!(
+            check_player_count(Some(&players(1, 20)), &arguments),
+            ErrorCode::Ok
+        );
+        assert_eq!(
+            check_player_count(Some(&players(20, 20)), &arguments),
+            ErrorCode::Ok
+        );
+    }
+
+    #[test]
+    fn test_fail_if_full() {
+        let arguments = CommandLineArguments {
+            fail_if_full: true,
+            ..CommandLineArguments::default()
+        };
+        assert_eq!(
+            check_player_count(Some(&players(20, 20)), &arguments),
+            ErrorCode::PlayerCount
+        );
+        assert_eq!(
+            check_player_count(Some(&players(21, 20)), &arguments),
+            ErrorCode::PlayerCount
+        );
+        assert_eq!(
+            check_player_count(Some(&players(19, 20)), &arguments),
+            ErrorCode::Ok
+        );
+        assert_eq!(
+            check_player_count(Some(&players(0, 20)), &arguments),
+            ErrorCode::Ok
+        );
+    }
+
+    #[test]
+    fn test_unknown_player_count() {
+        let arguments = CommandLineArguments {
+            fail_on_empty: true,
+            fail_if_full: true,
+            ..CommandLineArguments::default()
+        };
+        assert_eq!(check_player_count(None, &arguments), ErrorCode::Ok);
+    }
+}
+
+#[cfg(test)]
+mod format_optional_bool_tests {
+    use super::*;
+
+    #[test]
+    fn test_format_present_fields() {
+        assert_eq!(format_optional_bool(Some(true)), "Yes");
+        assert_eq!(format_optional_bool(Some(false)), "No");
+    }
+
+    #[test]
+    fn test_format_absent_field() {
+        let response: Response = serde_json::from_str(r#"{"description": ""}"#).unwrap();
+        assert_eq!(format_optional_bool(response.previews_chat), "Unknown");
+    }
+
+    #[test]
+    fn test_style_without_colors() {
+        assert_eq!(style_optional_bool(Some(true), false), "Yes");
+        assert_eq!(style_optional_bool(Some(false), false), "No");
+        assert_eq!(style_optional_bool(None, false), "Unknown");
+    }
+
+    #[test]
+    fn test_style_with_colors() {
+        assert_eq!(
+            style_optional_bool(Some(true), true),
+            format!("{FG_GREEN}Yes{RESET_COLORS}")
+        );
+        assert_eq!(
+            style_optional_bool(Some(false), true),
+            format!("{FG_RED}No{RESET_COLORS}")
+        );
+        assert_eq!(style_optional_bool(None, true), "Unknown");
+    }
+}
+
+#[cfg(test)]
+mod favicon_tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_crc32_empty() {
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn test_split_png_data_uri() {
+        assert_eq!(
+            split_favicon_data_uri("data:image/png;base64,iVBORw0KGgo="),
+            Some(("png", "iVBORw0KGgo="))
+        );
+    }
+
+    #[test]
+    fn test_split_jpeg_data_uri() {
+        assert_eq!(
+            split_favicon_data_uri("data:image/jpeg;base64,/9j/4AAQ"),
+            Some(("jpeg", "/9j/4AAQ"))
+        );
+    }
+
+    #[test]
+    fn test_split_unknown_favicon_format() {
+        assert_eq!(split_favicon_data_uri("iVBORw0KGgo="), None);
+        assert_eq!(split_favicon_data_uri("data:image/png,iVBORw0KGgo="), None);
+        assert_eq!(split_favicon_data_uri("data:text/plain;base64,aGk="), None);
+        assert_eq!(split_favicon_data_uri("data:image/;base64,aGk="), None);
+    }
+
+    #[test]
+    fn test_format_byte_size() {
+        assert_eq!(format_byte_size(0), "0 B");
+        assert_eq!(format_byte_size(1023), "1023 B");
+        assert_eq!(format_byte_size(1024), "1.0 KiB");
+        assert_eq!(format_byte_size(4300), "4.2 KiB");
+        assert_eq!(format_byte_size(3 * 1024 * 1024 / 2), "1.5 MiB");
+        assert_eq!(format_byte_size(1024 * 1024 * 1024), "1.0 GiB");
+    }
+
+    #[test]
+    fn test_write_favicon_hash() {
+        let mut output = vec![];
+        write_favicon(&mut output, b"123456789", true).unwrap();
+        assert_eq!(output, b"cbf43926\n");
+    }
+}
+
+#[cfg(test)]
+mod warning_tests {
+    use super::*;
+
+    #[test]
+    fn test_write_warning() {
+        let mut output = vec![];
+        let arguments = CommandLineArguments::default();
+        write_warning(&mut output, "Something odd", &arguments, false).unwrap();
+        assert_eq!(output, b"WARNING: Something odd\n");
+    }
+
+    #[test]
+    fn test_write_warning_with_colors() {
+        let mut output = vec![];
+        let arguments = CommandLineArguments::default();
+        write_warning(&mut output, "Something odd", &arguments, true).unwrap();
+        assert_eq!(output, b"\x1B[93mWARNING: Something odd\x1B[0m\n");
+    }
+
+    #[test]
+    fn test_quiet_hides_warnings() {
+        let mut output = vec![];
+        let arguments = CommandLineArguments {
+            quiet: true,
+            ..CommandLineArguments::default()
+        };
+        write_warning(&mut output, "Something odd", &arguments, false).unwrap();
+        assert!(output.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod lan_buffer_tests {
+    use super::*;
+
+    #[test]
+    fn test_full_buffer_may_be_truncated() {
+        assert!(may_be_truncated(LAN_BUFFER_SIZE, LAN_BUFFER_SIZE));
+    }
+
+    #[test]
+    fn test_partially_filled_buffer_is_complete() {
+        assert!(!may_be_truncated(0, LAN_BUFFER_SIZE));
+        assert!(!may_be_truncated(LAN_BUFFER_SIZE - 1, LAN_BUFFER_SIZE));
+    }
+}
+
+#[cfg(test)]
+mod timestamp_tests {
+    use super::*;
+
+    #[test]
+    fn test_format_epoch() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let time = UNIX_EPOCH + Duration::from_secs(1706725800);
+        assert_eq!(format_timestamp(time), "2024-01-31T18:30:00Z");
+    }
+
+    #[test]
+    fn test_format_leap_day() {
+        let time = UNIX_EPOCH + Duration::from_secs(1709251199);
+        assert_eq!(format_timestamp(time), "2024-02-29T23:59:59Z");
+    }
+}
+
+#[cfg(test)]
+mod color_depth_tests {
+    use super::*;
+
+    #[test]
+    fn test_truecolor() {
+        let env = |name: &str| (name == "COLORTERM").then(|| "truecolor".to_owned());
+        assert_eq!(detect_color_depth(env), "truecolor");
+        let env = |name: &str| (name == "COLORTERM").then(|| "24bit".to_owned());
+        assert_eq!(detect_color_depth(env), "truecolor");
+    }
+
+    #[test]
+    fn test_256_colors() {
+        let env = |name: &str| (name == "TERM").then(|| "xterm-256color".to_owned());
+        assert_eq!(detect_color_depth(env), "256");
+    }
+
+    #[test]
+    fn test_16_colors() {
+        assert_eq!(detect_color_depth(|_| None), "16");
+        let env = |name: &str| (name == "TERM").then(|| "xterm".to_owned());
+        assert_eq!(detect_color_depth(env), "16");
+    }
+}
+
+#[cfg(test)]
+mod exit_code_tests {
+    use super::*;
+
+    #[test]
+    fn test_status_checks_decide_by_default() {
+        let arguments = CommandLineArguments::default();
+        assert_eq!(
+            exit_code_after_ping(true, ErrorCode::PlayerCount, &arguments),
+            ErrorCode::PlayerCount
+        );
+        assert_eq!(
+            exit_code_after_ping(false, ErrorCode::PlayerCount, &arguments),
+            ErrorCode::Protocol
+        );
+    }
+
+    #[test]
+    fn test_fail_on_mismatch_only() {
+        let arguments = CommandLineArguments {
+            fail_on_mismatch_only: true,
+            ..CommandLineArguments::default()
+        };
+        assert_eq!(
+            exit_code_after_ping(true, ErrorCode::UnexpectedVersion, &arguments),
+            ErrorCode::Ok
+        );
+        assert_eq!(
+            exit_code_after_ping(false, ErrorCode::Ok, &arguments),
+            ErrorCode::Protocol
+        );
+    }
+}
+
+#[cfg(test)]
+mod response_protocol_tests {
+    use super::*;
+
+    const STATUS_JSON: &str = r#"{"description":"A Minecraft Server"}"#;
+
+    // Hands out a single byte on every read, like a server whose answer is split in many segments
+    struct OneByteReader<'a>(&'a [u8]);
+
+    impl Read for OneByteReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let length = self.0.len().min(buf.len()).min(1);
+            buf[..length].copy_from_slice(&self.0[..length]);
+            self.0 = &self.0[length..];
+            Ok(length)
+        }
+    }
+
+    #[test]
+    fn test_modern_response() {
+        let mut body = vec![];
+        write_string(&mut body, STATUS_JSON).unwrap();
+        let mut response = vec![];
+        write_packet(&mut response, 0, &body).unwrap();
+
+        let mut reader = BufReader::new(response.as_slice());
+        let protocol = peek_response_protocol(&mut reader).unwrap();
+        assert_eq!(protocol, ResponseProtocol::Modern);
+        // The whole packet is still there for the real parser
+        assert_eq!(
+            read_status_response(&mut reader, DEFAULT_MAX_PACKET_SIZE, false).unwrap(),
+            STATUS_JSON
+        );
+    }
+
+    #[test]
+    fn test_modern_response_with_long_length() {
+        // A 255 bytes long packet also starts with 0xFF
+        let mut reader = BufReader::new([0xFF, 0x01, 0x00].as_slice());
+        let protocol = peek_response_protocol(&mut reader).unwrap();
+        assert_eq!(protocol, ResponseProtocol::Modern);
+    }
+
+    #[test]
+    fn test_legacy_kick() {
+        // Kick packet with the message "§1" in UTF-16BE
+        let kick = [0xFF, 0x00, 0x02, 0x00, 0xA7, 0x00, 0x31];
+        let mut reader = BufReader::new(kick.as_slice());
+        let protocol = peek_response_protocol(&mut reader).unwrap();
+        assert_eq!(protocol, ResponseProtocol::Legacy);
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, kick);
+    }
+
+    #[test]
+    fn test_bytes_arriving_one_at_a_time() {
+        let mut body = vec![];
+        write_string(&mut body, STATUS_JSON).unwrap();
+        let mut response = vec![];
+        write_packet(&mut response, 0, &body).unwrap();
+
+        let mut reader = BufReader::new(OneByteReader(&response));
+        let protocol = peek_response_protocol(&mut reader).unwrap();
+        assert_eq!(protocol, ResponseProtocol::Modern);
+        assert_eq!(
+            read_status_response(&mut reader, DEFAULT_MAX_PACKET_SIZE, false).unwrap(),
+            STATUS_JSON
+        );
+    }
+
+    #[test]
+    fn test_peeking_twice() {
+        let kick = [0xFF, 0x00, 0x02, 0x00, 0xA7, 0x00, 0x31];
+        let mut reader = BufReader::new(kick.as_slice());
+        peek_response_protocol(&mut reader).unwrap();
+        let protocol = peek_response_protocol(&mut reader).unwrap();
+        assert_eq!(protocol, ResponseProtocol::Legacy);
+    }
+
+    #[test]
+    fn test_closed_connection() {
+        let mut reader = BufReader::new(io::empty());
+        let error = peek_response_protocol(&mut reader).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
 }
@@ -0,0 +1,146 @@
+use crate::data_types::read_var_int;
+use std::io::{self, stderr, Read, Write};
+
+// Wraps one direction of a connection and prints every complete packet that goes through it to stderr. Packets are
+// split using the length prefix, so this only works while the connection doesn't use compression or encryption.
+pub struct PacketDumper<T> {
+    inner: T,
+    direction: &'static str,
+    pending: Vec<u8>,
+}
+
+impl<T> PacketDumper<T> {
+    pub fn new(inner: T, direction: &'static str) -> Self {
+        PacketDumper {
+            inner,
+            direction,
+            pending: vec![],
+        }
+    }
+
+    fn record(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        for packet in take_complete_packets(&mut self.pending) {
+            let _ = stderr().write_all(format_packet(self.direction, &packet).as_bytes());
+        }
+    }
+}
+
+impl<R: Read> Read for PacketDumper<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.inner.read(buf)?;
+        self.record(&buf[..length]);
+        Ok(length)
+    }
+}
+
+impl<W: Write> Write for PacketDumper<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = self.inner.write(buf)?;
+        self.record(&buf[..length]);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn take_complete_packets(pending: &mut Vec<u8>) -> Vec<Vec<u8>> {
+    // Removes every packet whose bytes are all available and returns them without the length prefix
+    let mut packets = vec![];
+    loop {
+        let mut rest = pending.as_slice();
+        let packet_length = match read_var_int(&mut rest) {
+            Ok(length) => length,
+            // The length prefix is incomplete, or it isn't a VarInt at all. Don't get stuck on the latter.
+            Err(_) if pending.len() >= 5 => {
+                packets.push(std::mem::take(pending));
+                break;
+            }
+            Err(_) => break,
+        };
+        let prefix_length = pending.len() - rest.len();
+        let packet_length = usize::try_from(packet_length).unwrap_or(0);
+        if rest.len() < packet_length {
+            break;
+        }
+        packets.push(rest[..packet_length].to_vec());
+        pending.drain(..prefix_length + packet_length);
+    }
+    packets
+}
+
+fn format_packet(direction: &str, packet: &[u8]) -> String {
+    let packet_id = match read_var_int(&mut &packet[..]) {
+        Ok(packet_id) => format!("0x{packet_id:02x}"),
+        Err(_) => "unknown".to_owned(),
+    };
+    format!(
+        "{direction} packet {packet_id}, {} bytes\n{}",
+        packet.len(),
+        hex_dump(packet)
+    )
+}
+
+fn hex_dump(bytes: &[u8]) -> String {
+    // 16 bytes per line: offset, bytes in hexadecimal and the printable ASCII characters
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02x}")).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|byte| {
+                if byte.is_ascii_graphic() || *byte == b' ' {
+                    *byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        dump.push_str(&format!(
+            "{:08x}  {:<47}  |{ascii}|\n",
+            line * 16,
+            hex.join(" ")
+        ));
+    }
+    dump
+}
+
+#[cfg(test)]
+mod packet_dump_tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_dump() {
+        let expected = "00000000  00 01 41 42 43 7f 20 7a                          |..ABC. z|\n";
+        assert_eq!(
+            hex_dump(&[0x00, 0x01, b'A', b'B', b'C', 0x7f, b' ', b'z']),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_hex_dump_multiple_lines() {
+        let bytes: Vec<u8> = (b'a'..=b'r').collect();
+        let expected =
+            "00000000  61 62 63 64 65 66 67 68 69 6a 6b 6c 6d 6e 6f 70  |abcdefghijklmnop|\n\
+                        00000010  71 72                                            |qr|\n";
+        assert_eq!(hex_dump(&bytes), expected);
+    }
+
+    #[test]
+    fn test_format_packet() {
+        let expected = "Sent packet 0x01, 3 bytes\n00000000  01 ff 00                                         |...|\n";
+        assert_eq!(format_packet("Sent", &[0x01, 0xff, 0x00]), expected);
+    }
+
+    #[test]
+    fn test_take_complete_packets() {
+        let mut pending = vec![0x01, 0x00, 0x02, 0x01, 0x2a, 0x03, 0x00];
+        let packets = take_complete_packets(&mut pending);
+        assert_eq!(packets, [vec![0x00], vec![0x01, 0x2a]]);
+        // The last packet is still missing two bytes
+        assert_eq!(pending, [0x03, 0x00]);
+    }
+}
@@ -0,0 +1,153 @@
+// Protocol version sent in the handshake when the user doesn't ask for a specific one
+pub const MIN_MINECRAFT_PROTOCOL_VERSION: i32 = 0;
+
+// Known Minecraft Java Edition releases and the protocol number they use. Several releases may share the same protocol
+// number. To support a new release just append it to the end of the table.
+const PROTOCOL_VERSIONS: &[(&str, i32)] = &[
+    ("1.7.2", 4),
+    ("1.7.4", 4),
+    ("1.7.5", 4),
+    ("1.7.6", 5),
+    ("1.7.7", 5),
+    ("1.7.8", 5),
+    ("1.7.9", 5),
+    ("1.7.10", 5),
+    ("1.8", 47),
+    ("1.8.1", 47),
+    ("1.8.2", 47),
+    ("1.8.3", 47),
+    ("1.8.4", 47),
+    ("1.8.5", 47),
+    ("1.8.6", 47),
+    ("1.8.7", 47),
+    ("1.8.8", 47),
+    ("1.8.9", 47),
+    ("1.9", 107),
+    ("1.9.1", 108),
+    ("1.9.2", 109),
+    ("1.9.3", 110),
+    ("1.9.4", 110),
+    ("1.10", 210),
+    ("1.10.1", 210),
+    ("1.10.2", 210),
+    ("1.11", 315),
+    ("1.11.1", 316),
+    ("1.11.2", 316),
+    ("1.12", 335),
+    ("1.12.1", 338),
+    ("1.12.2", 340),
+    ("1.13", 393),
+    ("1.13.1", 401),
+    ("1.13.2", 404),
+    ("1.14", 477),
+    ("1.14.1", 480),
+    ("1.14.2", 485),
+    ("1.14.3", 490),
+    ("1.14.4", 498),
+    ("1.15", 573),
+    ("1.15.1", 575),
+    ("1.15.2", 578),
+    ("1.16", 735),
+    ("1.16.1", 736),
+    ("1.16.2", 751),
+    ("1.16.3", 753),
+    ("1.16.4", 754),
+    ("1.16.5", 754),
+    ("1.17", 755),
+    ("1.17.1", 756),
+    ("1.18", 757),
+    ("1.18.1", 757),
+    ("1.18.2", 758),
+    ("1.19", 759),
+    ("1.19.1", 760),
+    ("1.19.2", 760),
+    ("1.19.3", 761),
+    ("1.19.4", 762),
+    ("1.20", 763),
+    ("1.20.1", 763),
+    ("1.20.2", 764),
+    ("1.20.3", 765),
+    ("1.20.4", 765),
+    ("1.20.5", 766),
+    ("1.20.6", 766),
+    ("1.21", 767),
+    ("1.21.1", 767),
+    ("1.21.2", 768),
+    ("1.21.3", 768),
+    ("1.21.4", 769),
+    ("1.21.5", 770),
+    ("1.21.6", 771),
+    ("1.21.7", 772),
+    ("1.21.8", 772),
+];
+
+pub fn protocol_for_version(version: &str) -> Option<i32> {
+    PROTOCOL_VERSIONS
+        .iter()
+        .find(|(name, _)| *name == version)
+        .map(|(_, protocol)| *protocol)
+}
+
+// Releases that use the given protocol number, as a single version or a range like "1.20.3-1.20.4". Releases sharing
+// a protocol number are next to each other in the table.
+pub fn versions_for_protocol(protocol: i32) -> Option<String> {
+    let mut versions = PROTOCOL_VERSIONS
+        .iter()
+        .filter(|(_, version_protocol)| *version_protocol == protocol)
+        .map(|(name, _)| *name);
+    let first = versions.next()?;
+    match versions.next_back() {
+        Some(last) => Some(format!("{first}-{last}")),
+        None => Some(first.to_owned()),
+    }
+}
+
+// Protocol number of the newest release we know about
+pub fn latest_protocol_version() -> i32 {
+    PROTOCOL_VERSIONS
+        .last()
+        .map(|(_, protocol)| *protocol)
+        .unwrap_or(MIN_MINECRAFT_PROTOCOL_VERSION)
+}
+
+#[cfg(test)]
+mod versions_tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_for_version_1_8_9() {
+        assert_eq!(protocol_for_version("1.8.9"), Some(47));
+    }
+
+    #[test]
+    fn test_protocol_for_version_1_20_4() {
+        assert_eq!(protocol_for_version("1.20.4"), Some(765));
+    }
+
+    #[test]
+    fn test_protocol_for_unknown_version() {
+        assert_eq!(protocol_for_version("1.99"), None);
+    }
+
+    #[test]
+    fn test_versions_for_protocol_single_release() {
+        assert_eq!(versions_for_protocol(762), Some("1.19.4".to_owned()));
+    }
+
+    #[test]
+    fn test_versions_for_protocol_range() {
+        assert_eq!(versions_for_protocol(765), Some("1.20.3-1.20.4".to_owned()));
+        assert_eq!(versions_for_protocol(47), Some("1.8-1.8.9".to_owned()));
+    }
+
+    #[test]
+    fn test_versions_for_unknown_protocol() {
+        assert_eq!(versions_for_protocol(1), None);
+        assert_eq!(versions_for_protocol(-1), None);
+    }
+
+    #[test]
+    fn test_latest_protocol_version() {
+        assert_eq!(latest_protocol_version(), 772);
+    }
+}
@@ -0,0 +1,109 @@
+// Runs the mping binary against an in-process server that speaks the status protocol, so the whole pipeline from the
+// handshake to the pong is exercised over a real connection
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::{Command, Output};
+use std::thread;
+
+const STATUS_JSON: &str = r#"{"version":{"name":"1.20.4","protocol":765},"players":{"max":20,"online":3},"description":"A Minecraft Server"}"#;
+
+fn write_var_int<T: Write>(output: &mut T, value: i32) {
+    let mut value = value as u32;
+    loop {
+        let segment = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.write_all(&[segment]).unwrap();
+            return;
+        }
+        output.write_all(&[segment | 0x80]).unwrap();
+    }
+}
+
+fn read_var_int<T: Read>(input: &mut T) -> i32 {
+    let mut value: u32 = 0;
+    for i in 0..5 {
+        let mut byte = [0; 1];
+        input.read_exact(&mut byte).unwrap();
+        value |= ((byte[0] & 0x7F) as u32) << (i * 7);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    value as i32
+}
+
+fn read_raw_packet<T: Read>(input: &mut T) -> Vec<u8> {
+    let packet_length = read_var_int(input);
+    let mut packet = vec![0; packet_length as usize];
+    input.read_exact(&mut packet).unwrap();
+    packet
+}
+
+fn write_raw_packet<T: Write>(output: &mut T, packet: &[u8]) {
+    write_var_int(output, packet.len() as i32);
+    output.write_all(packet).unwrap();
+}
+
+fn serve_status(mut stream: TcpStream) {
+    // Handshake and status request
+    let handshake = read_raw_packet(&mut stream);
+    assert_eq!(handshake[0], 0x00);
+    assert_eq!(read_raw_packet(&mut stream), [0x00]);
+
+    // Status response
+    let mut body = vec![0x00];
+    write_var_int(&mut body, STATUS_JSON.len() as i32);
+    body.extend_from_slice(STATUS_JSON.as_bytes());
+    write_raw_packet(&mut stream, &body);
+
+    // Echo the ping request back as the pong response
+    let ping = read_raw_packet(&mut stream);
+    write_raw_packet(&mut stream, &ping);
+}
+
+fn spawn_mock_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve_status(stream);
+    });
+    address
+}
+
+fn run_mping(address: SocketAddr, flags: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mping"))
+        .args(flags)
+        .arg(address.ip().to_string())
+        .arg(address.port().to_string())
+        .env_remove("MCPING_HOST")
+        .env_remove("MCPING_DEFAULT_PORT")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_raw_response_from_mock_server() {
+    let address = spawn_mock_server();
+    let output = run_mping(address, &["-r"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim_end(),
+        STATUS_JSON
+    );
+}
+
+#[test]
+fn test_formatted_status_from_mock_server() {
+    let address = spawn_mock_server();
+    let output = run_mping(
+        address,
+        &["--format", "{version} {protocol} {online}/{max} {motd}"],
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim_end(),
+        "1.20.4 765 3/20 A Minecraft Server"
+    );
+}